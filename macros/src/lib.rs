@@ -0,0 +1,137 @@
+use proc_macro::TokenStream;
+use quote::{format_ident, quote};
+use syn::parse::{Parse, ParseStream};
+use syn::punctuated::Punctuated;
+use syn::{parse_macro_input, Expr, ExprLit, FnArg, Ident, ItemFn, Lit, LitStr, Meta, Pat, Path, Token};
+
+/// Marks a function as callable from scripts.
+///
+/// The function's doc comment becomes its description and its parameter names become the
+/// pin names. Pass the function to `module_fns!` to register it.
+///
+/// ```ignore
+/// /// Linearly interpolates between two values.
+/// #[script_function(category = "Math/Float", pure)]
+/// fn lerp(from: f32, to: f32, t: f32) -> f32 {
+///     from + (to - from) * t
+/// }
+///
+/// app.register_script_functions(module_fns![lerp]);
+/// ```
+///
+/// Supported arguments are `category`, `name` (defaults to the function's name), `id`,
+/// `version`, `returns` (the doc of the return value) and the flag `pure`.
+///
+/// The expansion refers to `::bevy_lek_scripting`, use the macro through its re-export there.
+#[proc_macro_attribute]
+pub fn script_function(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(attr with Punctuated::<Meta, Token![,]>::parse_terminated);
+    let function = parse_macro_input!(item as ItemFn);
+    match expand_script_function(args, &function) {
+        Ok(companion) => quote!(#function #companion).into(),
+        Err(err) => err.into_compile_error().into(),
+    }
+}
+
+fn expand_script_function(args: Punctuated<Meta, Token![,]>, function: &ItemFn) -> syn::Result<proc_macro2::TokenStream> {
+    let ident = &function.sig.ident;
+    let mut name = LitStr::new(&ident.to_string(), ident.span());
+    let mut descriptor = quote!(::bevy_lek_scripting::registry::FunctionDescriptor::new());
+    for arg in args {
+        match &arg {
+            Meta::Path(path) if path.is_ident("pure") => descriptor = quote!(#descriptor.pure()),
+            Meta::NameValue(name_value) => {
+                let key = name_value.path.get_ident().map(|a| a.to_string()).unwrap_or_default();
+                match (key.as_str(), &name_value.value) {
+                    ("name", Expr::Lit(ExprLit { lit: Lit::Str(value), .. })) => name = value.clone(),
+                    ("category", Expr::Lit(ExprLit { lit: Lit::Str(value), .. })) => {
+                        descriptor = quote!(#descriptor.with_category(#value))
+                    }
+                    ("id", Expr::Lit(ExprLit { lit: Lit::Str(value), .. })) => {
+                        descriptor = quote!(#descriptor.with_id(#value))
+                    }
+                    ("returns", Expr::Lit(ExprLit { lit: Lit::Str(value), .. })) => {
+                        descriptor = quote!(#descriptor.with_return_doc(#value))
+                    }
+                    ("version", Expr::Lit(ExprLit { lit: Lit::Int(value), .. })) => {
+                        descriptor = quote!(#descriptor.with_version(#value))
+                    }
+                    _ => return Err(syn::Error::new_spanned(&arg, "unknown script_function argument")),
+                }
+            }
+            _ => return Err(syn::Error::new_spanned(&arg, "unknown script_function argument")),
+        }
+    }
+
+    let description = doc_comment(function);
+    if !description.is_empty() {
+        descriptor = quote!(#descriptor.with_description(#description));
+    }
+    for input in &function.sig.inputs {
+        let FnArg::Typed(typed) = input else {
+            return Err(syn::Error::new_spanned(input, "script functions can't take `self`"));
+        };
+        let param = match &*typed.pat {
+            Pat::Ident(pat_ident) => pat_ident.ident.to_string(),
+            _ => return Err(syn::Error::new_spanned(&typed.pat, "script function parameters need a name")),
+        };
+        descriptor = quote!(#descriptor.with_param(#param, ""));
+    }
+
+    let vis = &function.vis;
+    let companion = companion_ident(ident);
+    Ok(quote! {
+        #[doc(hidden)]
+        #vis fn #companion() -> ::bevy_lek_scripting::registry::ScriptFunction {
+            ::bevy_lek_scripting::registry::ScriptFunction {
+                name: #name,
+                function: ::bevy_lek_scripting::registry::ReflectedFunction::new(#ident),
+                descriptor: #descriptor,
+            }
+        }
+    })
+}
+
+/// The `///` lines of the function, paragraphs are kept apart.
+fn doc_comment(function: &ItemFn) -> String {
+    let mut lines = vec![];
+    for attr in &function.attrs {
+        if !attr.path().is_ident("doc") {
+            continue;
+        }
+        if let Meta::NameValue(name_value) = &attr.meta {
+            if let Expr::Lit(ExprLit { lit: Lit::Str(line), .. }) = &name_value.value {
+                lines.push(line.value().trim().to_string());
+            }
+        }
+    }
+    lines.join("\n").trim().to_string()
+}
+
+fn companion_ident(ident: &Ident) -> Ident {
+    format_ident!("__script_function_{}", ident)
+}
+
+struct ModuleFns(Punctuated<Path, Token![,]>);
+
+impl Parse for ModuleFns {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        Ok(ModuleFns(Punctuated::parse_terminated(input)?))
+    }
+}
+
+/// Collects functions marked with `#[script_function]` into a `Vec<ScriptFunction>`.
+///
+/// ```ignore
+/// app.register_script_functions(module_fns![lerp, math::clamp]);
+/// ```
+#[proc_macro]
+pub fn module_fns(input: TokenStream) -> TokenStream {
+    let ModuleFns(paths) = parse_macro_input!(input as ModuleFns);
+    let companions = paths.into_iter().map(|mut path| {
+        let last = path.segments.last_mut().unwrap();
+        last.ident = companion_ident(&last.ident);
+        quote!(#path())
+    });
+    quote!(vec![#(#companions),*]).into()
+}
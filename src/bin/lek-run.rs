@@ -0,0 +1,141 @@
+//! Compiles a graph and runs it against a scene without a window.
+//!
+//! ```text
+//! lek-run <graph.lekgraph> <scene.scn.ron> [--ticks N] [--out dump.scn.ron]
+//! ```
+//!
+//! A `.lekprogram` saved by the editor can be passed instead of the graph, it is run as is.
+//!
+//! Prints the bytecode, runs the graph once per tick and dumps the scene's entities as a scene
+//! file, to stdout unless `--out` is given. Exits with 1 if anything fails.
+
+use bevy::ecs::entity::EntityHashMap;
+use bevy::prelude::*;
+use bevy::scene::serde::{SceneDeserializer, SceneSerializer};
+use bevy::scene::DynamicSceneBuilder;
+use bevy_lek_scripting::compiler::compile;
+use bevy_lek_scripting::graph_file::load_graph;
+use bevy_lek_scripting::program::load_program;
+use bevy_lek_scripting::registry::{ComponentMap, FunctionRegistry, MainThreadFunctions, RegistryPlugin};
+use bevy_lek_scripting::virtual_machine::{run, uses_main_thread, Bytecode};
+use bevy_lek_scripting::BuiltinFunctionsPlugin;
+use serde::de::DeserializeSeed;
+
+const USAGE: &str = "usage: lek-run <graph.lekgraph|program.lekprogram> <scene.scn.ron> [--ticks N] [--out dump.scn.ron]";
+
+struct Args {
+    graph: String,
+    scene: String,
+    ticks: usize,
+    out: Option<String>,
+}
+
+impl Args {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut paths = vec![];
+        let mut ticks = 1;
+        let mut out = None;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--ticks" => {
+                    ticks = args
+                        .next()
+                        .and_then(|ticks| ticks.parse().ok())
+                        .ok_or("--ticks needs a number")?;
+                }
+                "--out" => out = Some(args.next().ok_or("--out needs a path")?),
+                _ => paths.push(arg),
+            }
+        }
+        let [graph, scene] = <[String; 2]>::try_from(paths).map_err(|_| USAGE.to_string())?;
+        Ok(Args { graph, scene, ticks, out })
+    }
+}
+
+fn main() {
+    if let Err(err) = lek_run() {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    }
+}
+
+fn lek_run() -> Result<(), String> {
+    let args = Args::parse(std::env::args().skip(1))?;
+
+    let mut app = App::new();
+    app.add_plugins((MinimalPlugins, RegistryPlugin, BuiltinFunctionsPlugin));
+    app.finish();
+    app.cleanup();
+    load_scene(&args.scene, app.world_mut())?;
+    // fills the component map with the scene's components
+    app.update();
+
+    let program = {
+        let world = app.world();
+        let function_registry = world.resource::<FunctionRegistry>();
+        let type_registry = world.resource::<AppTypeRegistry>().read();
+        let component_map = world.resource::<ComponentMap>();
+        if args.graph.ends_with(".lekprogram") {
+            load_program(&args.graph, function_registry, &type_registry, component_map).map_err(|err| format!("couldn't load {}: {}", args.graph, err))?
+        } else {
+            let snarl = load_graph(&args.graph, function_registry, &type_registry, component_map)
+                .map_err(|err| format!("couldn't load {}: {}", args.graph, err))?;
+            compile(&snarl, function_registry).map_err(|err| format!("compile error: {}", err))?
+        }
+    };
+    for (index, instruction) in program.iter().enumerate() {
+        println!("{:>4} {:?}", index, instruction);
+    }
+
+    for tick in 0..args.ticks {
+        run_tick(&program, app.world_mut()).map_err(|err| format!("script error on tick {}: {}", tick, err))?;
+        app.update();
+    }
+
+    let dump = dump_entities(app.world())?;
+    match args.out {
+        Some(out) => std::fs::write(&out, dump).map_err(|err| format!("couldn't write {}: {}", out, err))?,
+        None => println!("{}", dump),
+    }
+    Ok(())
+}
+
+fn load_scene(path: &str, world: &mut World) -> Result<(), String> {
+    let source = std::fs::read_to_string(path).map_err(|err| format!("couldn't read {}: {}", path, err))?;
+    let type_registry = world.resource::<AppTypeRegistry>().clone();
+    let scene = {
+        let type_registry = type_registry.read();
+        let mut deserializer = ron::de::Deserializer::from_str(&source).map_err(|err| format!("couldn't parse {}: {}", path, err))?;
+        SceneDeserializer {
+            type_registry: &type_registry,
+        }
+        .deserialize(&mut deserializer)
+        .map_err(|err| format!("couldn't parse {}: {}", path, err))?
+    };
+    scene
+        .write_to_world(world, &mut EntityHashMap::default())
+        .map_err(|err| format!("couldn't spawn {}: {}", path, err))
+}
+
+/// Runs the program once, like the editor's "compile and run script" button.
+fn run_tick(program: &[Bytecode], world: &mut World) -> Result<(), String> {
+    world.resource_scope(|world, function_registry: Mut<FunctionRegistry>| {
+        let mut main_thread = uses_main_thread(program, &function_registry)
+            .then(|| world.remove_non_send_resource::<MainThreadFunctions>())
+            .flatten();
+        let result = run(program, &function_registry, main_thread.as_mut(), world);
+        if let Some(main_thread) = main_thread {
+            world.insert_non_send_resource(main_thread);
+        }
+        result.map_err(|err| err.to_string())
+    })
+}
+
+fn dump_entities(world: &World) -> Result<String, String> {
+    let scene = DynamicSceneBuilder::from_world(world)
+        .extract_entities(world.iter_entities().map(|entity| entity.id()))
+        .build();
+    let type_registry = world.resource::<AppTypeRegistry>().read();
+    ron::ser::to_string_pretty(&SceneSerializer::new(&scene, &type_registry), ron::ser::PrettyConfig::default())
+        .map_err(|err| format!("couldn't serialize the scene: {}", err))
+}
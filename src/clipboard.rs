@@ -0,0 +1,35 @@
+use std::collections::HashSet;
+use bevy::prelude::Resource;
+use bevy::reflect::TypeRegistry;
+use bevy_egui::egui::Vec2;
+use egui_snarl::{NodeId, Snarl};
+use crate::graph_file::{GraphFile, GraphFileError};
+use crate::registry::{ComponentMap, FunctionRegistry};
+use crate::scripting::ScriptNode;
+
+/// The nodes selected in the editor, what gets copied.
+#[derive(Resource, Default)]
+pub struct NodeSelection(pub HashSet<NodeId>);
+
+/// The selected nodes and the wires between them as a graph file fragment.
+pub fn copy_selection(snarl: &Snarl<ScriptNode>, selection: &NodeSelection, type_registry: &TypeRegistry) -> Result<String, GraphFileError> {
+    let fragment = GraphFile::from_nodes(snarl, |node| selection.0.contains(&node), type_registry)?;
+    Ok(serde_json::to_string_pretty(&fragment)?)
+}
+
+/// Inserts a copied fragment a bit off from where it was copied and selects the new nodes.
+///
+/// Components and functions are looked up again, so fragments can move between graphs and runs.
+pub fn paste(
+    text: &str,
+    snarl: &mut Snarl<ScriptNode>,
+    selection: &mut NodeSelection,
+    function_registry: &FunctionRegistry,
+    type_registry: &TypeRegistry,
+    component_map: &ComponentMap,
+) -> Result<(), GraphFileError> {
+    let fragment: GraphFile = serde_json::from_str(text)?;
+    let pasted = fragment.insert_into(snarl, Vec2::splat(40.0), function_registry, type_registry, component_map)?;
+    selection.0 = pasted.into_iter().collect();
+    Ok(())
+}
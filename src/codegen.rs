@@ -0,0 +1,343 @@
+use std::fmt::{Display, Formatter, Write};
+use bevy::reflect::{Reflect, ReflectRef};
+use egui_snarl::Snarl;
+use crate::compiler::{compile, CompileError};
+use crate::registry::{FunctionKind, FunctionRegistry};
+use crate::scripting::{remove_before_double_colon, ScriptNode};
+use crate::virtual_machine::Bytecode;
+
+#[derive(Debug)]
+pub enum CodegenError {
+    Compile(CompileError),
+    UnknownFunction(String),
+    /// The program uses something that has no native equivalent yet.
+    Unsupported(String),
+}
+
+impl Display for CodegenError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CodegenError::Compile(err) => write!(f, "{}", err),
+            CodegenError::UnknownFunction(name) => write!(f, "no function named `{}` is registered", name),
+            CodegenError::Unsupported(what) => write!(f, "{} can't be turned into Rust", what),
+        }
+    }
+}
+
+impl std::error::Error for CodegenError {}
+
+impl From<CompileError> for CodegenError {
+    fn from(err: CompileError) -> Self {
+        CodegenError::Compile(err)
+    }
+}
+
+/// A slot of the VM's stack while generating, the stack is mirrored so the handles in the
+/// bytecode can be resolved to Rust expressions.
+#[derive(Clone)]
+enum Slot {
+    /// A place that can be read, borrowed or assigned to.
+    Place(String),
+    Ref(String),
+    Mut(String),
+    /// A clone of a place, moved into the next call.
+    Owned(String),
+}
+
+impl Slot {
+    fn place(&self) -> &str {
+        match self {
+            Slot::Place(place) | Slot::Ref(place) | Slot::Mut(place) | Slot::Owned(place) => place,
+        }
+    }
+
+    fn as_arg(&self) -> String {
+        match self {
+            Slot::Place(place) => place.clone(),
+            Slot::Ref(place) => format!("&{}", place),
+            Slot::Mut(place) => format!("&mut {}", place),
+            Slot::Owned(place) => format!("{}.clone()", place),
+        }
+    }
+}
+
+/// Compiles a graph and turns the program into a Bevy system, see [`generate_system`].
+pub fn generate_from_graph(snarl: &Snarl<ScriptNode>, function_registry: &FunctionRegistry, system_name: &str) -> Result<String, CodegenError> {
+    let program = compile(snarl, function_registry)?;
+    generate_system(&program, function_registry, system_name)
+}
+
+/// Generates a system that does what [`crate::virtual_machine::run`] does with the program.
+///
+/// The query becomes a typed `Query`, fields are accessed directly and functions are called by
+/// the path they were registered from. Types are written by their short path, so the file
+/// expects `bevy::prelude` and the script's own types to be in scope.
+pub fn generate_system(program: &[Bytecode], function_registry: &FunctionRegistry, system_name: &str) -> Result<String, CodegenError> {
+    let Some(Bytecode::Query { components }) = program.first() else {
+        return Err(CodegenError::Unsupported("a graph without a query node".to_string()));
+    };
+    let has_parameters = program.iter().any(|instruction| matches!(instruction, Bytecode::Parameter { .. }));
+
+    let mut query_types = components
+        .iter()
+        .map(|(_, _, type_info)| format!("&mut {}", remove_before_double_colon(type_info.type_path())))
+        .collect::<Vec<_>>();
+    let mut bindings = (0..components.len()).map(|i| format!("mut c{}", i)).collect::<Vec<_>>();
+    if has_parameters {
+        query_types.push("Option<&::bevy_lek_scripting::script_component::ScriptParameters>".to_string());
+        bindings.push("parameters".to_string());
+    }
+
+    let mut stack = (0..components.len())
+        .map(|i| Slot::Place(format!("(*c{})", i)))
+        .collect::<Vec<_>>();
+    let mut body = String::new();
+    let mut locals = 0;
+    for instruction in &program[1..] {
+        match instruction {
+            Bytecode::Push(value) => {
+                let crate::indirect_stack::StackValue::Owned(value) = value else {
+                    return Err(CodegenError::Unsupported("pushing a borrowed value".to_string()));
+                };
+                let local = format!("s{}", locals);
+                locals += 1;
+                writeln!(body, "        let mut {} = {};", local, literal(value.as_ref())?).unwrap();
+                stack.push(Slot::Place(local));
+            }
+            Bytecode::Pop => {
+                stack.pop();
+            }
+            Bytecode::Call(function) => {
+                let name = function_registry
+                    .resolve(function)
+                    .ok_or_else(|| CodegenError::UnknownFunction(function.clone()))?;
+                let registered = &function_registry.functions[name];
+                let FunctionKind::Reflected(reflected) = &registered.kind else {
+                    return Err(CodegenError::Unsupported(format!("the world or main-thread function `{}`", name)));
+                };
+                if reflected.rust_path().contains('{') {
+                    return Err(CodegenError::Unsupported(format!("the closure registered as `{}`", name)));
+                }
+                let path = rust_path(reflected.rust_path())
+                    .ok_or_else(|| CodegenError::Unsupported(format!("the qualified path `{}`", reflected.rust_path())))?;
+                let args = stack
+                    .split_off(stack.len() - registered.signature().arg_count())
+                    .iter()
+                    .map(Slot::as_arg)
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                if registered.signature().return_info().type_path() == "()" {
+                    writeln!(body, "        {}({});", path, args).unwrap();
+                } else {
+                    let local = format!("s{}", locals);
+                    locals += 1;
+                    writeln!(body, "        let mut {} = {}({});", local, path, args).unwrap();
+                    stack.push(Slot::Place(local));
+                }
+            }
+            Bytecode::GetField(handle, name) => {
+                let parent = stack[handle.0].place().to_string();
+                stack.push(Slot::Place(format!("{}.{}", parent, name)));
+            }
+            Bytecode::SetField(handle) => {
+                let source = stack.pop().unwrap();
+                let target = stack[handle.0].place();
+                writeln!(body, "        {} = {}.clone();", target, source.place()).unwrap();
+            }
+            Bytecode::Query { .. } | Bytecode::SelfEntity { .. } => {
+                return Err(CodegenError::Unsupported("a second root node".to_string()));
+            }
+            Bytecode::Copy(handle) => stack.push(Slot::Owned(stack[handle.0].place().to_string())),
+            Bytecode::Ref(handle) => stack.push(Slot::Ref(stack[handle.0].place().to_string())),
+            Bytecode::Mut(handle) => stack.push(Slot::Mut(stack[handle.0].place().to_string())),
+            Bytecode::Parameter { name, default } => {
+                let local = format!("s{}", locals);
+                locals += 1;
+                let type_path = remove_before_double_colon(default.reflect_type_path());
+                writeln!(
+                    body,
+                    "        let mut {}: {} = parameters.and_then(|parameters| parameters.0.get({:?})).and_then(|value| value.downcast_ref::<{}>()).cloned().unwrap_or({});",
+                    local, type_path, name, type_path, literal(default.as_ref())?
+                )
+                .unwrap();
+                stack.push(Slot::Place(local));
+            }
+        }
+    }
+
+    let mut source = String::new();
+    writeln!(source, "// generated from a lek graph, edit the graph instead").unwrap();
+    writeln!(source, "use bevy::prelude::*;").unwrap();
+    writeln!(source).unwrap();
+    writeln!(source, "#[allow(unused_mut, unused_variables)]").unwrap();
+    writeln!(source, "pub fn {}(mut query: Query<({},)>) {{", system_name, query_types.join(", ")).unwrap();
+    writeln!(source, "    for ({},) in query.iter_mut() {{", bindings.join(", ")).unwrap();
+    source.push_str(&body);
+    writeln!(source, "    }}").unwrap();
+    writeln!(source, "}}").unwrap();
+    Ok(source)
+}
+
+/// Turns a function's type name into an expression path, `None` for `<T as Trait>::f`.
+///
+/// Functions of this crate are called through `::bevy_lek_scripting`, which also resolves
+/// inside it. Generic arguments get a turbofish, `foo<f32>` becomes `foo::<f32>`.
+fn rust_path(type_name: &str) -> Option<String> {
+    if type_name.starts_with('<') {
+        return None;
+    }
+    let mut path = String::new();
+    if type_name.starts_with(concat!(env!("CARGO_CRATE_NAME"), "::")) {
+        path.push_str("::");
+    }
+    let mut depth = 0;
+    let mut previous = ' ';
+    for c in type_name.chars() {
+        match c {
+            '<' => {
+                if depth == 0 && previous != ':' {
+                    path.push_str("::");
+                }
+                depth += 1;
+            }
+            // the arrow of a `fn() -> T` argument doesn't close anything
+            '>' if previous != '-' => depth -= 1,
+            _ => {}
+        }
+        path.push(c);
+        previous = c;
+    }
+    Some(path)
+}
+
+/// A Rust expression constructing `value`.
+fn literal(value: &dyn Reflect) -> Result<String, CodegenError> {
+    macro_rules! floats {
+        ($($ty:ty),*) => {
+            $(if let Some(number) = value.downcast_ref::<$ty>() {
+                let ty = stringify!($ty);
+                return Ok(if number.is_nan() {
+                    format!("{}::NAN", ty)
+                } else if number.is_infinite() {
+                    format!("{}::{}", ty, if *number > 0.0 { "INFINITY" } else { "NEG_INFINITY" })
+                } else {
+                    format!("{:?}_{}", number, ty)
+                });
+            })*
+        };
+    }
+    macro_rules! numbers {
+        ($($ty:ty),*) => {
+            $(if let Some(number) = value.downcast_ref::<$ty>() {
+                return Ok(format!("{:?}_{}", number, stringify!($ty)));
+            })*
+        };
+    }
+    floats!(f32, f64);
+    numbers!(i8, i16, i32, i64, u8, u16, u32, u64, isize, usize);
+    if let Some(boolean) = value.downcast_ref::<bool>() {
+        return Ok(boolean.to_string());
+    }
+    if let Some(string) = value.downcast_ref::<String>() {
+        return Ok(format!("String::from({:?})", string));
+    }
+    let type_path = remove_before_double_colon(value.reflect_type_path());
+    // the fields of SIMD glam types are private, go through their constructors
+    if value.reflect_type_path().starts_with("glam::") {
+        let constructor = match type_path.as_str() {
+            "Quat" | "DQuat" => "from_xyzw",
+            name if name.contains("Vec") => "new",
+            _ => return Err(CodegenError::Unsupported(format!("a value of `{}`", value.reflect_type_path()))),
+        };
+        let ReflectRef::Struct(dyn_struct) = value.reflect_ref() else {
+            return Err(CodegenError::Unsupported(format!("a value of `{}`", value.reflect_type_path())));
+        };
+        let fields = dyn_struct.iter_fields().map(literal).collect::<Result<Vec<_>, _>>()?;
+        return Ok(format!("{}::{}({})", type_path, constructor, fields.join(", ")));
+    }
+    match value.reflect_ref() {
+        ReflectRef::Struct(dyn_struct) => {
+            let mut fields = vec![];
+            for (index, field) in dyn_struct.iter_fields().enumerate() {
+                fields.push(format!("{}: {}", dyn_struct.name_at(index).unwrap(), literal(field)?));
+            }
+            Ok(format!("{} {{ {} }}", type_path, fields.join(", ")))
+        }
+        ReflectRef::TupleStruct(tuple_struct) => {
+            let fields = tuple_struct.iter_fields().map(literal).collect::<Result<Vec<_>, _>>()?;
+            Ok(format!("{}({})", type_path, fields.join(", ")))
+        }
+        ReflectRef::Tuple(tuple) => {
+            let fields = tuple.iter_fields().map(literal).collect::<Result<Vec<_>, _>>()?;
+            Ok(format!("({},)", fields.join(", ")))
+        }
+        _ => Err(CodegenError::Unsupported(format!("a value of `{}`", value.reflect_type_path()))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::component::ComponentId;
+    use bevy::ecs::system::RunSystemOnce;
+    use bevy::reflect::Typed;
+    use crate::indirect_stack::{StackHandle, StackValue};
+    use crate::registry::FunctionDescriptor;
+    use crate::test_support::{position_world, sub, Position};
+    use crate::virtual_machine::run;
+    use super::*;
+
+    /// What [`generate_system`] makes of [`program`], checked in so it is compiled with the tests.
+    #[allow(clippy::all)]
+    mod generated {
+        use crate::test_support::Position;
+        include!("codegen_test_system.rs");
+    }
+
+    /// `position.x = sub(position.y, 2.0)`
+    fn program(component_id: ComponentId) -> Vec<Bytecode> {
+        vec![
+            Bytecode::Query {
+                components: vec![("Position".to_string(), component_id, Position::type_info().clone())],
+            },
+            Bytecode::Push(StackValue::Owned(Box::new(2.0_f32))),
+            Bytecode::GetField(StackHandle(0), "y".to_string()),
+            Bytecode::Copy(StackHandle(2)),
+            Bytecode::Copy(StackHandle(1)),
+            Bytecode::Call("sub".to_string()),
+            Bytecode::GetField(StackHandle(0), "x".to_string()),
+            Bytecode::Ref(StackHandle(3)),
+            Bytecode::SetField(StackHandle(4)),
+        ]
+    }
+
+    #[test]
+    fn generated_system_does_what_the_vm_does() {
+        let mut function_registry = FunctionRegistry::default();
+        function_registry.register("sub", sub, FunctionDescriptor::new());
+        let (mut vm_world, component_map) = position_world();
+        let component_id = component_map.0[&std::any::TypeId::of::<Position>()].id;
+        let program = program(component_id);
+        assert_eq!(
+            generate_system(&program, &function_registry, "position_system").unwrap(),
+            include_str!("codegen_test_system.rs")
+        );
+
+        let (mut native_world, _) = position_world();
+        let vm_entity = vm_world.spawn(Position { x: 0.0, y: 5.0 }).id();
+        let native_entity = native_world.spawn(Position { x: 0.0, y: 5.0 }).id();
+        run(&program, &function_registry, None, &mut vm_world).unwrap();
+        native_world.run_system_once(generated::position_system);
+        assert_eq!(vm_world.get::<Position>(vm_entity), native_world.get::<Position>(native_entity));
+        assert_eq!(vm_world.get::<Position>(vm_entity).unwrap().x, 3.0);
+    }
+
+    #[test]
+    fn generic_functions_are_called_with_a_turbofish() {
+        assert_eq!(rust_path("bevy_lek_scripting::lerp<f32>").unwrap(), "::bevy_lek_scripting::lerp::<f32>");
+        assert_eq!(
+            rust_path("my_game::apply<alloc::vec::Vec<f32>, fn(f32) -> f32>").unwrap(),
+            "my_game::apply::<alloc::vec::Vec<f32>, fn(f32) -> f32>"
+        );
+        assert!(rust_path("<my_game::Foo as my_game::Bar>::baz").is_none());
+    }
+}
@@ -0,0 +1,11 @@
+// generated from a lek graph, edit the graph instead
+use bevy::prelude::*;
+
+#[allow(unused_mut, unused_variables)]
+pub fn position_system(mut query: Query<(&mut Position,)>) {
+    for (mut c0,) in query.iter_mut() {
+        let mut s0 = 2.0_f32;
+        let mut s1 = ::bevy_lek_scripting::test_support::sub((*c0).y.clone(), s0.clone());
+        (*c0).x = s1.clone();
+    }
+}
@@ -1,44 +1,93 @@
 use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
 use std::hash::Hash;
 use bevy::ecs::component::ComponentId;
 use bevy::prelude::Node;
 use bevy::reflect::func::Arg;
+use bevy::reflect::func::args::Ownership;
 use bevy::reflect::TypeInfo;
 use egui_snarl::{InPinId, NodeId, OutPinId, Snarl};
 use egui_snarl::ui::PinInfo;
-use crate::indirect_stack::StackValue;
-use crate::scripting::{FieldNode, FunctionNode, QueryNode, ScriptNode, SetNode, TypeCreationNode};
-use crate::virtual_machine::Bytecode;
+use crate::hot_reload::{call_site_problem, unresolved_problem, CallSiteProblem};
+use crate::indirect_stack::{StackHandle, StackValue};
+use crate::registry::FunctionRegistry;
+use crate::scripting::{FieldNode, FunctionNode, FunctionReturn, ParameterNode, QueryNode, ScriptNode, SetNode, TypeCreationNode};
+use crate::virtual_machine::{parameters, Bytecode};
 
-pub fn compile(snarl: &Snarl<ScriptNode>) -> Vec<Bytecode> {
-    // we gotta find the roots, rn i'm just gonna look for the query
-    let mut query_n = None;
-    for (node_id, node) in snarl.node_ids() {
-        match node {
-            ScriptNode::Query(_) => {
-                query_n.replace(node_id);
+#[derive(Debug)]
+pub enum CompileError {
+    /// The graph needs a query or a self node to start from.
+    MissingRoot,
+    UnknownFunction(String),
+    NoMatchingOverload {
+        overload: String,
+        /// The inferred type of every argument, `None` if it couldn't be inferred.
+        arg_types: Vec<Option<String>>,
+    },
+    AmbiguousOverload {
+        overload: String,
+        candidates: Vec<String>,
+    },
+    /// The node doesn't match the registered function anymore and has to be migrated.
+    OutdatedCallSite {
+        node: NodeId,
+        function: String,
+        problem: CallSiteProblem,
+    },
+    /// A data input the node needs has no wire, e.g. a hole `_` in a text script.
+    UnwiredInput {
+        node: NodeId,
+        input: usize,
+    },
+    /// A field node without a field picked.
+    UnnamedField(NodeId),
+    /// Two parameter nodes share a name, entities couldn't override them separately.
+    DuplicateParameter(String),
+}
+
+impl Display for CompileError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CompileError::MissingRoot => write!(f, "the graph has neither a query nor a self node"),
+            CompileError::UnknownFunction(name) => write!(f, "no function named `{}` is registered", name),
+            CompileError::NoMatchingOverload { overload, arg_types } => {
+                let arg_types = arg_types
+                    .iter()
+                    .map(|a| a.as_deref().unwrap_or("?"))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                write!(f, "no overload of `{}` takes ({})", overload, arg_types)
             }
-            _ => continue,
+            CompileError::AmbiguousOverload { overload, candidates } => write!(
+                f,
+                "call to `{}` is ambiguous between {}",
+                overload,
+                candidates.join(", ")
+            ),
+            CompileError::OutdatedCallSite { node, function, problem } => {
+                write!(f, "node {:?}: `{}` {}, migrate the node", node, function, problem)
+            }
+            CompileError::UnwiredInput { node, input } => write!(f, "node {:?}: nothing is wired into input {}", node, input),
+            CompileError::UnnamedField(node) => write!(f, "node {:?}: no field is picked", node),
+            CompileError::DuplicateParameter(name) => write!(f, "more than one parameter is named `{}`", name),
         }
     }
+}
 
-    let mut wire_stuff = WireStuff::default();
-    for (out_pin_id, in_pin_id) in snarl.wires() {
-        if !wire_stuff.input_map.contains_key(&in_pin_id.node) {
-            wire_stuff.input_map.insert(in_pin_id.node, vec![]);
+impl std::error::Error for CompileError {}
+
+pub fn compile(snarl: &Snarl<ScriptNode>, function_registry: &FunctionRegistry) -> Result<Vec<Bytecode>, CompileError> {
+    // the flow can't be followed through a placeholder, so it can't be left for the loop below
+    for (node, script_node) in snarl.node_ids() {
+        if let ScriptNode::Unresolved(unresolved) = script_node {
+            return Err(CompileError::OutdatedCallSite {
+                node,
+                function: unresolved.name().to_string(),
+                problem: unresolved_problem(unresolved, function_registry),
+            });
         }
-        if !wire_stuff.output_map.contains_key(&out_pin_id.node) {
-            wire_stuff.output_map.insert(out_pin_id.node, vec![]);
-        }
-        if !wire_stuff.pin_map.contains_key(&out_pin_id) {
-            wire_stuff.pin_map.insert(out_pin_id, vec![]);
-        }
-        wire_stuff.input_map.get_mut(&in_pin_id.node).unwrap().push(in_pin_id);
-        wire_stuff.output_map.get_mut(&out_pin_id.node).unwrap().push(out_pin_id);
-        wire_stuff.pin_map.get_mut(&out_pin_id).unwrap().push(in_pin_id);
-        wire_stuff.pin_map_2.insert(in_pin_id, out_pin_id);
     }
-    let query_n = query_n.unwrap();
+    let (query_n, wire_stuff) = root_and_wires(snarl)?;
 
     let mut nodes_already_computed = HashSet::default();
 
@@ -52,11 +101,14 @@ pub fn compile(snarl: &Snarl<ScriptNode>) -> Vec<Bytecode> {
     let mut current_stack: usize = 0;
     loop {
         match tree.script_node {
-            ScriptNode::Set(set_n) => set_node(tree.node_id, set_n, &mut second_wire_stuff, &mut bytecode, &mut current_stack),
-            ScriptNode::Field(field_n) => field_node(tree.node_id, field_n, &mut second_wire_stuff, &mut bytecode, &mut current_stack),
-            ScriptNode::Function(function_n) => function_node(tree.node_id, function_n, &mut second_wire_stuff, &mut bytecode, &mut current_stack),
+            ScriptNode::Set(set_n) => set_node(tree.node_id, set_n, &mut second_wire_stuff, &mut bytecode, &mut current_stack)?,
+            ScriptNode::Field(field_n) => field_node(tree.node_id, field_n, &mut second_wire_stuff, &mut bytecode, &mut current_stack)?,
+            ScriptNode::Function(function_n) => function_node(tree.node_id, function_n, function_registry, &mut second_wire_stuff, &mut bytecode, &mut current_stack)?,
             ScriptNode::TypeCreation(type_creation_n) => type_creation_node(tree.node_id, type_creation_n, &mut second_wire_stuff, &mut bytecode, &mut current_stack),
-            ScriptNode::Query(query_n) => query_node(tree.node_id, query_n, &mut second_wire_stuff, &mut bytecode, &mut current_stack)
+            ScriptNode::Parameter(parameter_n) => parameter_node(tree.node_id, parameter_n, &mut second_wire_stuff, &mut bytecode, &mut current_stack),
+            ScriptNode::Query(query_n) => query_node(tree.node_id, query_n, false, &mut second_wire_stuff, &mut bytecode, &mut current_stack),
+            ScriptNode::SelfEntity(query_n) => query_node(tree.node_id, query_n, true, &mut second_wire_stuff, &mut bytecode, &mut current_stack),
+            ScriptNode::Unresolved(_) => unreachable!("rejected before compiling"),
         }
         tree = match tree.left {
             None => break,
@@ -66,47 +118,203 @@ pub fn compile(snarl: &Snarl<ScriptNode>) -> Vec<Bytecode> {
         }
     }
 
-    bytecode
+    let mut parameter_names = HashSet::new();
+    for (name, _) in parameters(&bytecode) {
+        if !parameter_names.insert(name) {
+            return Err(CompileError::DuplicateParameter(name.to_string()));
+        }
+    }
+
+    Ok(bytecode)
+
+}
 
+/// Finds the root and indexes the wires by node and pin.
+fn root_and_wires(snarl: &Snarl<ScriptNode>) -> Result<(NodeId, WireStuff), CompileError> {
+    // we gotta find the roots, rn i'm just gonna look for the query
+    let mut query_n = None;
+    for (node_id, node) in snarl.node_ids() {
+        match node {
+            ScriptNode::Query(_) | ScriptNode::SelfEntity(_) => {
+                query_n.replace(node_id);
+            }
+            _ => continue,
+        }
+    }
+
+    let mut wire_stuff = WireStuff::default();
+    for (out_pin_id, in_pin_id) in snarl.wires() {
+        if !wire_stuff.input_map.contains_key(&in_pin_id.node) {
+            wire_stuff.input_map.insert(in_pin_id.node, vec![]);
+        }
+        if !wire_stuff.output_map.contains_key(&out_pin_id.node) {
+            wire_stuff.output_map.insert(out_pin_id.node, vec![]);
+        }
+        if !wire_stuff.pin_map.contains_key(&out_pin_id) {
+            wire_stuff.pin_map.insert(out_pin_id, vec![]);
+        }
+        wire_stuff.input_map.get_mut(&in_pin_id.node).unwrap().push(in_pin_id);
+        wire_stuff.output_map.get_mut(&out_pin_id.node).unwrap().push(out_pin_id);
+        wire_stuff.pin_map.get_mut(&out_pin_id).unwrap().push(in_pin_id);
+        wire_stuff.pin_map_2.insert(in_pin_id, out_pin_id);
+    }
+    let query_n = query_n.ok_or(CompileError::MissingRoot)?;
+    Ok((query_n, wire_stuff))
+}
+
+/// The nodes reachable from the root, in the order [`compile`] emits them.
+pub fn evaluation_order(snarl: &Snarl<ScriptNode>) -> Result<Vec<NodeId>, CompileError> {
+    let (query_n, wire_stuff) = root_and_wires(snarl)?;
+    let mut tree = compute_data_flow(query_n, &mut HashSet::default(), snarl, &wire_stuff);
+    let mut order = vec![tree.node_id];
+    while let Some(left) = tree.left {
+        tree = *left;
+        order.push(tree.node_id);
+    }
+    Ok(order)
 }
 
 
-fn function_node(node_id: NodeId, function_node: FunctionNode, wire_stuff: &mut SecondWireStuff, bytecode: &mut Vec<Bytecode>, current_stack: &mut usize) {
-    // skip flow node
-    for i in 1..(function_node.function_info.arg_count()+1) {
-        let arg_node = wire_stuff.get_data_info(InPinId {
+fn function_node(node_id: NodeId, function_node: FunctionNode, function_registry: &FunctionRegistry, wire_stuff: &mut SecondWireStuff, bytecode: &mut Vec<Bytecode>, current_stack: &mut usize) -> Result<(), CompileError> {
+    if let Some(problem) = call_site_problem(&function_node, function_registry) {
+        return Err(CompileError::OutdatedCallSite {
             node: node_id,
-            input: i,
-        }).unwrap();
-        bytecode.push(Bytecode::Copy(arg_node));
+            function: function_node.function_id().to_string(),
+            problem,
+        });
+    }
+    let name = match &function_node.overload {
+        None => function_registry
+            .resolve(function_node.function_id())
+            .ok_or_else(|| CompileError::UnknownFunction(function_node.function_id().to_string()))?
+            .to_string(),
+        Some(overload) => resolve_overload(node_id, overload, &function_node, function_registry, wire_stuff)?,
+    };
+    let registered = function_registry
+        .functions
+        .get(&name)
+        .ok_or_else(|| CompileError::UnknownFunction(name.clone()))?;
+    let function_info = registered.signature().clone();
+    // calls go by stable id so renames don't break compiled scripts
+    let id = registered.descriptor.id.clone().unwrap_or(name);
+    let mut args = function_info.args().iter().collect::<Vec<_>>();
+    args.sort_by_key(|arg| arg.index());
+    // push in parameter order, see `Bytecode::Call`
+    for arg in args {
+        let arg_node = wire_stuff.wired(InPinId {
+            node: node_id,
+            input: function_node.input_pin(arg),
+        })?;
+        // only clone when the function wants to own the value
+        bytecode.push(match arg.ownership() {
+            Ownership::Ref => Bytecode::Ref(arg_node),
+            Ownership::Mut => Bytecode::Mut(arg_node),
+            Ownership::Owned => Bytecode::Copy(arg_node),
+        });
         // we don't have to increase the stack because we are about to pop it all off for the function
     }
-    bytecode.push(Bytecode::Call(function_node.function_info.name().unwrap().to_string()));
-    wire_stuff.set_data_info(OutPinId {
-        node: node_id,
-        output: 1,
-    }, *current_stack);
-    *current_stack += 1;
+    bytecode.push(Bytecode::Call(id));
+    match &function_node.returns {
+        FunctionReturn::Unit => {}
+        FunctionReturn::Single(_) => {
+            let output = OutPinId {
+                node: node_id,
+                output: function_node.output_pin(0),
+            };
+            wire_stuff.set_data_info(output, StackHandle(*current_stack));
+            wire_stuff.set_data_type(output, function_info.return_info().type_path());
+            *current_stack += 1;
+        }
+        FunctionReturn::Tuple(elements) => {
+            // the tuple itself stays on the stack, every element pin references into it
+            let tuple = StackHandle(*current_stack);
+            *current_stack += 1;
+            for (i, element) in elements.iter().enumerate() {
+                bytecode.push(Bytecode::GetField(tuple, i.to_string()));
+                let output = OutPinId {
+                    node: node_id,
+                    output: function_node.output_pin(i),
+                };
+                wire_stuff.set_data_info(output, StackHandle(*current_stack));
+                wire_stuff.set_data_type(output, element);
+                *current_stack += 1;
+            }
+        }
+    }
+    Ok(())
+}
 
+/// Picks the overload whose parameter types match the types flowing into the node,
+/// arguments whose type can't be inferred match anything.
+fn resolve_overload(node_id: NodeId, overload: &str, function_node: &FunctionNode, function_registry: &FunctionRegistry, wire_stuff: &SecondWireStuff) -> Result<String, CompileError> {
+    let arg_types = (0..function_node.function_info.arg_count())
+        .map(|index| {
+            wire_stuff
+                .get_data_type(InPinId {
+                    node: node_id,
+                    input: function_node.input_pin_at(index),
+                })
+                .map(|a| a.to_string())
+        })
+        .collect::<Vec<_>>();
+    let mut candidates = vec![];
+    for name in function_registry.overloads.get(overload).into_iter().flatten() {
+        let Some(function) = function_registry.functions.get(name) else {
+            continue;
+        };
+        let matches = function.signature().args().iter().all(|arg| {
+            match arg_types.get(arg.index()).and_then(|a| a.as_deref()) {
+                None => true,
+                Some(arg_type) => strip_reference(arg.type_path()) == arg_type,
+            }
+        });
+        if matches {
+            candidates.push(name.clone());
+        }
+    }
+    match candidates.len() {
+        0 => Err(CompileError::NoMatchingOverload {
+            overload: overload.to_string(),
+            arg_types,
+        }),
+        1 => Ok(candidates.pop().unwrap()),
+        _ => Err(CompileError::AmbiguousOverload {
+            overload: overload.to_string(),
+            candidates,
+        }),
+    }
+}
+
+/// The type behind a `&T` or `&mut T` parameter.
+pub(crate) fn strip_reference(type_path: &str) -> &str {
+    type_path
+        .strip_prefix("&mut ")
+        .or_else(|| type_path.strip_prefix('&'))
+        .unwrap_or(type_path)
 }
-fn field_node(node_id: NodeId, field_node: FieldNode, wire_stuff: &mut SecondWireStuff, bytecode: &mut Vec<Bytecode>, current_stack: &mut usize) {
-    let struct_node = wire_stuff.get_data_info(InPinId {
+
+fn field_node(node_id: NodeId, field_node: FieldNode, wire_stuff: &mut SecondWireStuff, bytecode: &mut Vec<Bytecode>, current_stack: &mut usize) -> Result<(), CompileError> {
+    let struct_node = wire_stuff.wired(InPinId {
         node: node_id,
         input: 0,
-    }).unwrap();
+    })?;
 
-    wire_stuff.set_data_info(OutPinId {
+    let output = OutPinId {
         node: node_id,
         output: 0,
-    }, *current_stack);
+    };
+    wire_stuff.set_data_info(output, StackHandle(*current_stack));
+    if let Some(field) = &field_node.field {
+        wire_stuff.set_data_type(output, field.type_path());
+    }
 
-    let name = field_node.name.unwrap();
+    let name = field_node.name.ok_or(CompileError::UnnamedField(node_id))?;
     bytecode.push(Bytecode::GetField(struct_node, name));
     *current_stack += 1;
-
+    Ok(())
 }
 
-fn set_node(node_id: NodeId, set_node: SetNode, wire_stuff: &mut SecondWireStuff, bytecode: &mut Vec<Bytecode>, current_stack: &mut usize) {
+fn set_node(node_id: NodeId, set_node: SetNode, wire_stuff: &mut SecondWireStuff, bytecode: &mut Vec<Bytecode>, current_stack: &mut usize) -> Result<(), CompileError> {
     let field_set_id = InPinId {
         node: node_id,
         input: 1,
@@ -116,9 +324,12 @@ fn set_node(node_id: NodeId, set_node: SetNode, wire_stuff: &mut SecondWireStuff
         node: node_id,
         input: 2,
     };
-    bytecode.push(Bytecode::Copy(wire_stuff.get_data_info(field_get_id).unwrap()));
-    bytecode.push(Bytecode::SetField(wire_stuff.get_data_info(field_set_id).unwrap()));
-    // what we copied to set gets automatically popped off what we copied to set.
+    let source = wire_stuff.wired(field_get_id)?;
+    let target = wire_stuff.wired(field_set_id)?;
+    bytecode.push(Bytecode::Ref(source));
+    bytecode.push(Bytecode::SetField(target));
+    // the borrow we pushed to set gets automatically popped off by the set.
+    Ok(())
 }
 
 fn type_creation_node(node_id: NodeId, type_creation_node: TypeCreationNode, wire_stuff: &mut SecondWireStuff, bytecode: &mut Vec<Bytecode>, current_stack: &mut usize) {
@@ -126,22 +337,45 @@ fn type_creation_node(node_id: NodeId, type_creation_node: TypeCreationNode, wir
         node: node_id,
         output: 0,
     };
-    wire_stuff.set_data_info(place_where_type_is_on_stack, *current_stack);
+    wire_stuff.set_data_info(place_where_type_is_on_stack, StackHandle(*current_stack));
+    wire_stuff.set_data_type(place_where_type_is_on_stack, type_creation_node.value.reflect_type_path());
     *current_stack += 1;
     bytecode.push(Bytecode::Push(StackValue::Owned(type_creation_node.value)));
 }
 
-fn query_node(node_id: NodeId, query_node: QueryNode, wire_stuff: &mut SecondWireStuff, bytecode: &mut Vec<Bytecode>, current_stack: &mut usize) {
+fn parameter_node(node_id: NodeId, parameter_node: ParameterNode, wire_stuff: &mut SecondWireStuff, bytecode: &mut Vec<Bytecode>, current_stack: &mut usize) {
+    let output = OutPinId {
+        node: node_id,
+        output: 0,
+    };
+    wire_stuff.set_data_info(output, StackHandle(*current_stack));
+    wire_stuff.set_data_type(output, parameter_node.value.reflect_type_path());
+    *current_stack += 1;
+    // the value is only a default, the VM looks for an override first
+    bytecode.push(Bytecode::Parameter {
+        name: parameter_node.name,
+        default: parameter_node.value,
+    });
+}
+
+fn query_node(node_id: NodeId, query_node: QueryNode, self_entity: bool, wire_stuff: &mut SecondWireStuff, bytecode: &mut Vec<Bytecode>, current_stack: &mut usize) {
     for i in 1..(query_node.components.len() + 1) {
         let query_component_output = OutPinId {
             node: node_id,
             output: i,
         };
-        wire_stuff.set_data_info(query_component_output, *current_stack);
+        wire_stuff.set_data_info(query_component_output, StackHandle(*current_stack));
+        wire_stuff.set_data_type(query_component_output, query_node.components[i - 1].2.type_path());
         *current_stack += 1;
     }
-    bytecode.push(Bytecode::Query {
-        components: query_node.components,
+    bytecode.push(if self_entity {
+        Bytecode::SelfEntity {
+            components: query_node.components,
+        }
+    } else {
+        Bytecode::Query {
+            components: query_node.components,
+        }
     });
 }
 
@@ -250,7 +484,26 @@ struct SecondWireStuff {
     output_map: HashMap<NodeId, Vec<OutPinId>>,
     pin_map: HashMap<OutPinId, Vec<InPinId>>,
     pin_map_2: HashMap<InPinId, OutPinId>,
-    data_info: HashMap<OutPinId, usize>,
+    data_info: HashMap<OutPinId, StackHandle>,
+    data_types: HashMap<OutPinId, String>,
+}
+
+impl SecondWireStuff {
+    fn set_data_type(&mut self, pin: OutPinId, type_path: &str) {
+        self.data_types.insert(pin, type_path.to_string());
+    }
+
+    fn get_data_type(&self, pin: InPinId) -> Option<&str> {
+        self.data_types.get(self.pin_map_2.get(&pin)?).map(|a| a.as_str())
+    }
+
+    /// Where the value wired into `pin` is on the stack.
+    fn wired(&self, pin: InPinId) -> Result<StackHandle, CompileError> {
+        self.get_data_info(pin).ok_or(CompileError::UnwiredInput {
+            node: pin.node,
+            input: pin.input,
+        })
+    }
 }
 
 impl From<WireStuff> for SecondWireStuff {
@@ -261,18 +514,19 @@ impl From<WireStuff> for SecondWireStuff {
             pin_map,
             pin_map_2,
             data_info: Default::default(),
+            data_types: Default::default(),
         }
     }
 }
 
 pub trait Pin<T> {
-    fn get_data_info(&self, pin: T) -> Option<usize>;
+    fn get_data_info(&self, pin: T) -> Option<StackHandle>;
     fn has_data_info(&self, pin: T) -> bool;
-    fn set_data_info(&mut self, pin: T, stack_position: usize);
+    fn set_data_info(&mut self, pin: T, stack_position: StackHandle);
 }
 
 impl Pin<OutPinId> for SecondWireStuff {
-    fn get_data_info(&self, pin: OutPinId) -> Option<usize> {
+    fn get_data_info(&self, pin: OutPinId) -> Option<StackHandle> {
         self.data_info.get(&pin).map(|a| *a)
     }
 
@@ -280,13 +534,13 @@ impl Pin<OutPinId> for SecondWireStuff {
         self.data_info.contains_key(&pin)
     }
 
-    fn set_data_info(&mut self, pin: OutPinId, stack_position: usize) {
+    fn set_data_info(&mut self, pin: OutPinId, stack_position: StackHandle) {
         self.data_info.insert(pin, stack_position);
     }
 }
 
 impl Pin<InPinId> for SecondWireStuff {
-    fn get_data_info(&self, pin: InPinId) -> Option<usize> {
+    fn get_data_info(&self, pin: InPinId) -> Option<StackHandle> {
         self.get_data_info(*self.pin_map_2.get(&pin)?)
     }
 
@@ -294,7 +548,93 @@ impl Pin<InPinId> for SecondWireStuff {
         self.has_data_info(*self.pin_map_2.get(&pin).unwrap())
     }
 
-    fn set_data_info(&mut self, pin: InPinId, stack_position: usize) {
+    fn set_data_info(&mut self, pin: InPinId, stack_position: StackHandle) {
         self.set_data_info(*self.pin_map_2.get(&pin).unwrap(), stack_position)
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use bevy::reflect::TypeRegistry;
+    use crate::registry::FunctionDescriptor;
+    use super::*;
+
+    fn add_f32(a: f32, b: f32) -> f32 {
+        a + b
+    }
+
+    fn add_i32(a: i32, b: i32) -> i32 {
+        a + b
+    }
+
+    fn add_node(function_registry: &FunctionRegistry) -> FunctionNode {
+        let Some(ScriptNode::Function(function_node)) =
+            ScriptNode::registered_function("add", function_registry, &TypeRegistry::default())
+        else {
+            panic!("`add` isn't registered");
+        };
+        function_node
+    }
+
+    fn add_overloads() -> FunctionRegistry {
+        let mut function_registry = FunctionRegistry::default();
+        function_registry.register("add_f32", add_f32, FunctionDescriptor::new());
+        function_registry.register("add_i32", add_i32, FunctionDescriptor::new());
+        function_registry
+            .overloads
+            .insert("add".to_string(), vec!["add_f32".to_string(), "add_i32".to_string()]);
+        function_registry
+    }
+
+    /// Wires a value of each type into the node's parameters, in order.
+    fn wired_types(node: NodeId, function_node: &FunctionNode, types: &[&str]) -> SecondWireStuff {
+        let mut wire_stuff: SecondWireStuff = WireStuff::default().into();
+        for (index, type_path) in types.iter().enumerate() {
+            let source = OutPinId {
+                node: NodeId(index + 1),
+                output: 0,
+            };
+            wire_stuff.pin_map_2.insert(
+                InPinId {
+                    node,
+                    input: function_node.input_pin_at(index),
+                },
+                source,
+            );
+            wire_stuff.set_data_type(source, type_path);
+        }
+        wire_stuff
+    }
+
+    #[test]
+    fn overload_matching_the_argument_types_is_picked() {
+        let function_registry = add_overloads();
+        let function_node = add_node(&function_registry);
+        let wire_stuff = wired_types(NodeId(0), &function_node, &["i32", "i32"]);
+        let name = resolve_overload(NodeId(0), "add", &function_node, &function_registry, &wire_stuff).unwrap();
+        assert_eq!(name, "add_i32");
+    }
+
+    #[test]
+    fn no_overload_takes_mixed_arguments() {
+        let function_registry = add_overloads();
+        let function_node = add_node(&function_registry);
+        let wire_stuff = wired_types(NodeId(0), &function_node, &["f32", "i32"]);
+        assert!(matches!(
+            resolve_overload(NodeId(0), "add", &function_node, &function_registry, &wire_stuff),
+            Err(CompileError::NoMatchingOverload { .. })
+        ));
+    }
+
+    #[test]
+    fn arguments_of_unknown_type_are_ambiguous() {
+        let function_registry = add_overloads();
+        let function_node = add_node(&function_registry);
+        let wire_stuff = wired_types(NodeId(0), &function_node, &[]);
+        let Err(CompileError::AmbiguousOverload { candidates, .. }) =
+            resolve_overload(NodeId(0), "add", &function_node, &function_registry, &wire_stuff)
+        else {
+            panic!("expected the call to be ambiguous");
+        };
+        assert_eq!(candidates, vec!["add_f32".to_string(), "add_i32".to_string()]);
+    }
+}
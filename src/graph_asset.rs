@@ -0,0 +1,102 @@
+use bevy::asset::io::Reader;
+use bevy::asset::{AssetLoader, AsyncReadExt, LoadContext};
+use bevy::prelude::*;
+use crate::graph_file::{GraphFile, GraphFileError};
+use crate::hot_reload::{check_call_sites, ScriptStatus};
+use crate::registry::{ComponentMap, FunctionRegistry};
+use crate::SnarlResource;
+
+/// A `.lekgraph` file, resolved against the registries whenever it's (re)loaded.
+#[derive(Asset, TypePath, Clone)]
+pub struct Graph(pub GraphFile);
+
+#[derive(Default)]
+pub struct GraphLoader;
+
+impl AssetLoader for GraphLoader {
+    type Asset = Graph;
+    type Settings = ();
+    type Error = GraphFileError;
+
+    async fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader<'_>,
+        _settings: &'a (),
+        _load_context: &'a mut LoadContext<'_>,
+    ) -> Result<Graph, GraphFileError> {
+        let mut bytes = vec![];
+        reader.read_to_end(&mut bytes).await?;
+        Ok(Graph(serde_json::from_slice(&bytes)?))
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["lekgraph"]
+    }
+}
+
+/// The graph shown in the editor, replaced whenever its file changes on disk.
+#[derive(Resource)]
+pub struct MainGraph(pub Handle<Graph>);
+
+/// What the editor last saved to the main graph's file. The file watcher reports that save like
+/// any other change, reloading it would only replace the graph with itself and run it.
+#[derive(Resource, Default)]
+pub struct SavedMainGraph(pub Option<GraphFile>);
+
+pub struct GraphAssetPlugin;
+
+impl Plugin for GraphAssetPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<Graph>();
+        app.init_asset_loader::<GraphLoader>();
+        app.init_resource::<SavedMainGraph>();
+        app.add_systems(Startup, load_main_graph);
+        app.add_systems(Update, reload_main_graph);
+    }
+}
+
+fn load_main_graph(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(MainGraph(asset_server.load("main.lekgraph")));
+}
+
+/// Puts a (re)loaded main graph into the editor and runs it, so changes made in an external
+/// editor show up while the game runs. Reloads of what the editor saved itself are skipped.
+fn reload_main_graph(
+    mut commands: Commands,
+    mut events: EventReader<AssetEvent<Graph>>,
+    graphs: Res<Assets<Graph>>,
+    main_graph: Option<Res<MainGraph>>,
+    saved_main_graph: Res<SavedMainGraph>,
+    mut snarl: ResMut<SnarlResource>,
+    function_registry: Res<FunctionRegistry>,
+    type_registry: Res<AppTypeRegistry>,
+    component_map: Res<ComponentMap>,
+    mut script_status: ResMut<ScriptStatus>,
+) {
+    let Some(main_graph) = main_graph else {
+        return;
+    };
+    for event in events.read() {
+        let (AssetEvent::Added { id } | AssetEvent::Modified { id }) = event else {
+            continue;
+        };
+        if *id != main_graph.0.id() {
+            continue;
+        }
+        let Some(graph) = graphs.get(*id) else {
+            continue;
+        };
+        if saved_main_graph.0.as_ref() == Some(&graph.0) {
+            continue;
+        }
+        match graph.0.to_snarl(&function_registry, &type_registry.read(), &component_map) {
+            Ok(loaded) => {
+                snarl.0 = loaded;
+                script_status.broken_call_sites = check_call_sites(&snarl.0, &function_registry);
+                script_status.needs_recompile = true;
+                commands.run_system(unsafe { crate::SYSTEM_ID.unwrap() });
+            }
+            Err(err) => println!("couldn't load graph: {}", err),
+        }
+    }
+}
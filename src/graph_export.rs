@@ -0,0 +1,147 @@
+//! Renders graphs as Graphviz DOT or Mermaid for docs and reviews.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use egui_snarl::{InPinId, OutPinId, Snarl};
+use crate::compiler::evaluation_order;
+use crate::scripting::{FunctionNode, ScriptNode};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GraphFormat {
+    Dot,
+    Mermaid,
+}
+
+impl GraphFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            GraphFormat::Dot => "dot",
+            GraphFormat::Mermaid => "mmd",
+        }
+    }
+}
+
+/// Flow wires are drawn bold and data wires dashed, labelled with the input they feed.
+///
+/// Nodes are numbered in the order the compiler evaluates them, nodes it never reaches (or all of
+/// them if the graph doesn't compile) are unnumbered. Nodes and wires are sorted so the output
+/// only changes where the graph does.
+pub fn export_graph(snarl: &Snarl<ScriptNode>, format: GraphFormat) -> String {
+    let order = evaluation_order(snarl)
+        .map(|order| {
+            order
+                .into_iter()
+                .enumerate()
+                .map(|(index, node)| (node, index + 1))
+                .collect::<HashMap<_, _>>()
+        })
+        .unwrap_or_default();
+
+    let mut nodes = snarl.node_ids().collect::<Vec<_>>();
+    nodes.sort_by_key(|(node_id, _)| node_id.0);
+    let mut wires = snarl.wires().collect::<Vec<_>>();
+    wires.sort_by_key(|(out_pin, in_pin)| (out_pin.node.0, out_pin.output, in_pin.node.0, in_pin.input));
+
+    let mut out = String::new();
+    match format {
+        GraphFormat::Dot => {
+            writeln!(out, "digraph script {{").unwrap();
+            writeln!(out, "    rankdir=LR;").unwrap();
+            writeln!(out, "    node [shape=box];").unwrap();
+        }
+        GraphFormat::Mermaid => writeln!(out, "flowchart LR").unwrap(),
+    }
+    for (node_id, node) in nodes {
+        let label = match order.get(&node_id) {
+            Some(index) => format!("{}. {}", index, node_label(node)),
+            None => node_label(node),
+        };
+        match format {
+            GraphFormat::Dot => {
+                let style = if order.contains_key(&node_id) { "" } else { ", style=dashed" };
+                writeln!(out, "    n{} [label=\"{}\"{}];", node_id.0, escape_dot(&label), style).unwrap();
+            }
+            GraphFormat::Mermaid => writeln!(out, "    n{}[\"{}\"]", node_id.0, escape_mermaid(&label)).unwrap(),
+        }
+    }
+    for (out_pin, in_pin) in wires {
+        let from = out_pin.node.0;
+        let to = in_pin.node.0;
+        match (format, input_label(snarl, out_pin, in_pin)) {
+            (GraphFormat::Dot, None) => writeln!(out, "    n{} -> n{} [style=bold];", from, to).unwrap(),
+            (GraphFormat::Dot, Some(label)) if label.is_empty() => writeln!(out, "    n{} -> n{} [style=dashed];", from, to).unwrap(),
+            (GraphFormat::Dot, Some(label)) => writeln!(out, "    n{} -> n{} [style=dashed, label=\"{}\"];", from, to, escape_dot(&label)).unwrap(),
+            (GraphFormat::Mermaid, None) => writeln!(out, "    n{} ==> n{}", from, to).unwrap(),
+            (GraphFormat::Mermaid, Some(label)) if label.is_empty() => writeln!(out, "    n{} -.-> n{}", from, to).unwrap(),
+            (GraphFormat::Mermaid, Some(label)) => writeln!(out, "    n{} -. \"{}\" .-> n{}", from, escape_mermaid(&label), to).unwrap(),
+        }
+    }
+    if format == GraphFormat::Dot {
+        writeln!(out, "}}").unwrap();
+    }
+    out
+}
+
+pub fn write_graph(path: &str, snarl: &Snarl<ScriptNode>, format: GraphFormat) -> std::io::Result<()> {
+    std::fs::write(path, export_graph(snarl, format))
+}
+
+fn node_label(node: &ScriptNode) -> String {
+    match node {
+        ScriptNode::Set(_) => "set".to_string(),
+        ScriptNode::Field(field_node) => match (&field_node.name, &field_node.field) {
+            (Some(name), Some(field)) => format!(".{}: {}", name, field.type_path_table().short_path()),
+            _ => "field".to_string(),
+        },
+        ScriptNode::Function(function_node) => function_node
+            .function_info
+            .name()
+            .unwrap_or("unknown_function")
+            .to_string(),
+        ScriptNode::TypeCreation(type_creation_node) => format!("{:?}", type_creation_node.value),
+        ScriptNode::Parameter(parameter_node) => format!(
+            "parameter {}: {}",
+            parameter_node.name,
+            parameter_node.value.reflect_short_type_path()
+        ),
+        ScriptNode::Query(query_node) | ScriptNode::SelfEntity(query_node) => {
+            let keyword = match node {
+                ScriptNode::Query(_) => "query",
+                _ => "self",
+            };
+            let components = query_node
+                .components
+                .iter()
+                .map(|(name, _, _)| name.as_str())
+                .collect::<Vec<_>>();
+            format!("{} ({})", keyword, components.join(", "))
+        }
+        ScriptNode::Unresolved(unresolved) => format!("{} (unregistered)", unresolved.name()),
+    }
+}
+
+/// `None` for flow wires, otherwise the name of the input the value goes into.
+fn input_label(snarl: &Snarl<ScriptNode>, out_pin: OutPinId, in_pin: InPinId) -> Option<String> {
+    if out_pin.output == 0 && snarl[out_pin.node].can_flow() {
+        return None;
+    }
+    Some(match &snarl[in_pin.node] {
+        ScriptNode::Set(_) => match in_pin.input {
+            1 => "target".to_string(),
+            _ => "value".to_string(),
+        },
+        ScriptNode::Function(function_node) => function_node
+            .arg_at_pin(in_pin.input)
+            .map(FunctionNode::arg_name)
+            .unwrap_or_default(),
+        _ => String::new(),
+    })
+}
+
+fn escape_dot(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn escape_mermaid(label: &str) -> String {
+    label.replace('"', "#quot;")
+}
@@ -0,0 +1,478 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+use bevy::reflect::func::args::Ownership;
+use bevy::reflect::func::FunctionInfo;
+use bevy::reflect::serde::{ReflectDeserializer, ReflectSerializer};
+use bevy::reflect::{Reflect, ReflectFromReflect, TypeRegistry};
+use bevy_egui::egui::{Pos2, Vec2};
+use egui_snarl::{InPinId, NodeId, OutPinId, Snarl};
+use serde::de::DeserializeSeed;
+use serde::{Deserialize, Serialize};
+use crate::registry::{ComponentMap, FunctionRegistry};
+use crate::scripting::{remove_before_double_colon, FieldNode, ParameterNode, QueryNode, ScriptNode, SetNode, TypeCreationNode, UnresolvedNode};
+
+pub const GRAPH_FILE_VERSION: u32 = 1;
+
+/// A graph as stored on disk, loaded as a [`Graph`](crate::graph_asset::Graph) asset.
+///
+/// Runtime ids don't survive a restart, so types are stored by type path, functions by their
+/// stable id and values through `ReflectSerializer`.
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct GraphFile {
+    pub version: u32,
+    pub nodes: Vec<SavedNode>,
+    pub wires: Vec<SavedWire>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct SavedNode {
+    /// Only used to connect the wires.
+    pub id: usize,
+    pub pos: [f32; 2],
+    pub kind: SavedNodeKind,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub enum SavedNodeKind {
+    Set,
+    Field {
+        field: Option<String>,
+        name: Option<String>,
+    },
+    Function {
+        id: String,
+        version: u32,
+        overload: Option<String>,
+        /// Missing in files saved before signatures were stored.
+        #[serde(default)]
+        signature: Option<SavedSignature>,
+    },
+    TypeCreation {
+        value: serde_json::Value,
+    },
+    Parameter {
+        name: String,
+        value: serde_json::Value,
+    },
+    Query {
+        components: Vec<String>,
+    },
+    SelfEntity {
+        components: Vec<String>,
+    },
+}
+
+/// The signature a function node was made from, compared against the registered function on load.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SavedSignature {
+    /// In parameter order.
+    pub args: Vec<SavedArg>,
+    pub return_type: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SavedArg {
+    pub type_path: String,
+    /// `owned`, `ref` or `mut`.
+    pub ownership: String,
+}
+
+impl SavedSignature {
+    pub fn of(function_info: &FunctionInfo) -> Self {
+        let mut args = function_info.args().iter().collect::<Vec<_>>();
+        args.sort_by_key(|arg| arg.index());
+        SavedSignature {
+            args: args
+                .into_iter()
+                .map(|arg| SavedArg {
+                    type_path: arg.type_path().to_string(),
+                    ownership: match arg.ownership() {
+                        Ownership::Ref => "ref",
+                        Ownership::Mut => "mut",
+                        Ownership::Owned => "owned",
+                    }
+                    .to_string(),
+                })
+                .collect(),
+            return_type: function_info.return_info().type_path().to_string(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct SavedWire {
+    pub from: (usize, usize),
+    pub to: (usize, usize),
+}
+
+#[derive(Debug)]
+pub enum GraphFileError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    UnsupportedVersion(u32),
+    UnknownType(String),
+    UnknownComponent(String),
+    Value(String),
+}
+
+impl Display for GraphFileError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GraphFileError::Io(err) => write!(f, "{}", err),
+            GraphFileError::Json(err) => write!(f, "{}", err),
+            GraphFileError::UnsupportedVersion(version) => write!(f, "graph file version {} is not supported", version),
+            GraphFileError::UnknownType(type_path) => write!(f, "`{}` is not in the type registry", type_path),
+            GraphFileError::UnknownComponent(type_path) => write!(f, "`{}` is not a known component", type_path),
+            GraphFileError::Value(message) => write!(f, "couldn't read value: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for GraphFileError {}
+
+impl From<std::io::Error> for GraphFileError {
+    fn from(err: std::io::Error) -> Self {
+        GraphFileError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for GraphFileError {
+    fn from(err: serde_json::Error) -> Self {
+        GraphFileError::Json(err)
+    }
+}
+
+impl GraphFile {
+    pub fn from_snarl(snarl: &Snarl<ScriptNode>, type_registry: &TypeRegistry) -> Result<Self, GraphFileError> {
+        Self::from_nodes(snarl, |_| true, type_registry)
+    }
+
+    /// Stores the nodes `include` returns `true` for and the wires between them, e.g. a selection
+    /// copied to the clipboard.
+    pub fn from_nodes(snarl: &Snarl<ScriptNode>, include: impl Fn(NodeId) -> bool, type_registry: &TypeRegistry) -> Result<Self, GraphFileError> {
+        let mut nodes = vec![];
+        for (node_id, pos, node) in snarl.nodes_pos_ids() {
+            if !include(node_id) {
+                continue;
+            }
+            let kind = match node {
+                ScriptNode::Set(_) => SavedNodeKind::Set,
+                ScriptNode::Field(field_node) => SavedNodeKind::Field {
+                    field: field_node.field.as_ref().map(|a| a.type_path().to_string()),
+                    name: field_node.name.clone(),
+                },
+                ScriptNode::Function(function_node) => SavedNodeKind::Function {
+                    id: function_node.function_id().to_string(),
+                    version: function_node.descriptor.version,
+                    overload: function_node.overload.clone(),
+                    // a node that wasn't migrated yet keeps the signature it was loaded with
+                    signature: function_node
+                        .saved_signature
+                        .clone()
+                        .or_else(|| function_node.overload.is_none().then(|| SavedSignature::of(&function_node.function_info))),
+                },
+                ScriptNode::TypeCreation(type_creation) => SavedNodeKind::TypeCreation {
+                    value: serde_json::to_value(ReflectSerializer::new(type_creation.value.as_ref(), type_registry))?,
+                },
+                ScriptNode::Parameter(parameter_node) => SavedNodeKind::Parameter {
+                    name: parameter_node.name.clone(),
+                    value: serde_json::to_value(ReflectSerializer::new(parameter_node.value.as_ref(), type_registry))?,
+                },
+                ScriptNode::Query(query_node) => SavedNodeKind::Query {
+                    components: component_paths(query_node),
+                },
+                ScriptNode::SelfEntity(query_node) => SavedNodeKind::SelfEntity {
+                    components: component_paths(query_node),
+                },
+                ScriptNode::Unresolved(unresolved) => SavedNodeKind::Function {
+                    id: unresolved.id.clone(),
+                    version: unresolved.version,
+                    overload: unresolved.overload.clone(),
+                    signature: unresolved.signature.clone(),
+                },
+            };
+            nodes.push(SavedNode {
+                id: node_id.0,
+                pos: [pos.x, pos.y],
+                kind,
+            });
+        }
+        let wires = snarl
+            .wires()
+            .filter(|(out_pin, in_pin)| include(out_pin.node) && include(in_pin.node))
+            .map(|(out_pin, in_pin)| SavedWire {
+                from: (out_pin.node.0, out_pin.output),
+                to: (in_pin.node.0, in_pin.input),
+            })
+            .collect();
+        Ok(GraphFile {
+            version: GRAPH_FILE_VERSION,
+            nodes,
+            wires,
+        })
+    }
+
+    /// Resolves the stored paths and ids against the registries.
+    ///
+    /// Function nodes keep the version and signature they were saved with, so nodes of functions
+    /// that changed since show up as outdated call sites. Functions that aren't registered load as
+    /// [`UnresolvedNode`]s.
+    pub fn to_snarl(
+        &self,
+        function_registry: &FunctionRegistry,
+        type_registry: &TypeRegistry,
+        component_map: &ComponentMap,
+    ) -> Result<Snarl<ScriptNode>, GraphFileError> {
+        let mut snarl = Snarl::new();
+        self.insert_into(&mut snarl, Vec2::ZERO, function_registry, type_registry, component_map)?;
+        Ok(snarl)
+    }
+
+    /// Adds the nodes to an existing graph, moved by `offset`, and returns their new ids.
+    ///
+    /// Nothing is inserted if a node can't be resolved. Wires into pins a function node doesn't
+    /// have anymore, because the function's arity changed, are dropped.
+    pub fn insert_into(
+        &self,
+        snarl: &mut Snarl<ScriptNode>,
+        offset: Vec2,
+        function_registry: &FunctionRegistry,
+        type_registry: &TypeRegistry,
+        component_map: &ComponentMap,
+    ) -> Result<Vec<NodeId>, GraphFileError> {
+        if self.version != GRAPH_FILE_VERSION {
+            return Err(GraphFileError::UnsupportedVersion(self.version));
+        }
+        let mut resolved = vec![];
+        for saved in &self.nodes {
+            let node = match &saved.kind {
+                SavedNodeKind::Set => ScriptNode::Set(SetNode::new()),
+                SavedNodeKind::Field { field, name } => {
+                    let mut field_node = FieldNode::new();
+                    if let Some(type_path) = field {
+                        let registration = type_registry
+                            .get_with_type_path(type_path)
+                            .ok_or_else(|| GraphFileError::UnknownType(type_path.clone()))?;
+                        field_node.field = Some(registration.type_info().clone());
+                    }
+                    field_node.name = name.clone();
+                    ScriptNode::Field(field_node)
+                }
+                SavedNodeKind::Function { id, version, overload, signature } => {
+                    let name = match overload {
+                        Some(overload) => Some(overload.as_str()),
+                        None => function_registry.resolve(id),
+                    };
+                    let node = name.and_then(|name| ScriptNode::registered_function(name, function_registry, type_registry));
+                    match node {
+                        Some(ScriptNode::Function(mut function_node)) => {
+                            function_node.descriptor.version = *version;
+                            function_node.saved_signature = signature.clone();
+                            ScriptNode::Function(function_node)
+                        }
+                        _ => ScriptNode::Unresolved(UnresolvedNode {
+                            id: id.clone(),
+                            version: *version,
+                            overload: overload.clone(),
+                            signature: signature.clone(),
+                            inputs: wired_pins(self.wires.iter().map(|wire| wire.to), saved.id)
+                                .max(signature.as_ref().map_or(0, |signature| signature.args.len())),
+                            outputs: wired_pins(self.wires.iter().map(|wire| wire.from), saved.id),
+                        }),
+                    }
+                }
+                SavedNodeKind::TypeCreation { value } => {
+                    ScriptNode::TypeCreation(TypeCreationNode::new(deserialize_value(value, type_registry)?))
+                }
+                SavedNodeKind::Parameter { name, value } => {
+                    ScriptNode::Parameter(ParameterNode::new(name.clone(), deserialize_value(value, type_registry)?))
+                }
+                SavedNodeKind::Query { components } => {
+                    ScriptNode::Query(query_components(components, type_registry, component_map)?)
+                }
+                SavedNodeKind::SelfEntity { components } => {
+                    ScriptNode::SelfEntity(query_components(components, type_registry, component_map)?)
+                }
+            };
+            resolved.push((saved, node));
+        }
+        let mut ids = HashMap::new();
+        for (saved, node) in resolved {
+            let node_id = snarl.insert_node(Pos2::new(saved.pos[0], saved.pos[1]) + offset, node);
+            ids.insert(saved.id, node_id);
+        }
+        for wire in &self.wires {
+            let (Some(from), Some(to)) = (ids.get(&wire.from.0), ids.get(&wire.to.0)) else {
+                continue;
+            };
+            let missing_output = matches!(&snarl[*from], ScriptNode::Function(function_node) if wire.from.1 >= function_node.output_pin_count());
+            let missing_input = matches!(&snarl[*to], ScriptNode::Function(function_node) if wire.to.1 >= function_node.input_pin_count());
+            if missing_output || missing_input {
+                println!("dropped wire {:?} -> {:?}, the function's pins changed", wire.from, wire.to);
+                continue;
+            }
+            snarl.connect(
+                OutPinId {
+                    node: *from,
+                    output: wire.from.1,
+                },
+                InPinId {
+                    node: *to,
+                    input: wire.to.1,
+                },
+            );
+        }
+        Ok(ids.into_values().collect())
+    }
+}
+
+/// How many pins a node needs to keep every saved wire ending at it.
+fn wired_pins(ends: impl Iterator<Item = (usize, usize)>, node: usize) -> usize {
+    ends.filter(|(id, _)| *id == node)
+        .map(|(_, pin)| pin + 1)
+        .max()
+        .unwrap_or(0)
+}
+
+fn component_paths(query_node: &QueryNode) -> Vec<String> {
+    query_node
+        .components
+        .iter()
+        .map(|(_, _, type_info)| type_info.type_path().to_string())
+        .collect()
+}
+
+pub(crate) fn query_components(components: &[String], type_registry: &TypeRegistry, component_map: &ComponentMap) -> Result<QueryNode, GraphFileError> {
+    let mut query_node = QueryNode::new();
+    for type_path in components {
+        let registration = type_registry
+            .get_with_type_path(type_path)
+            .ok_or_else(|| GraphFileError::UnknownType(type_path.clone()))?;
+        let entry = component_map
+            .0
+            .get(&registration.type_id())
+            .ok_or_else(|| GraphFileError::UnknownComponent(type_path.clone()))?;
+        query_node.components.push((
+            remove_before_double_colon(type_path),
+            entry.id,
+            registration.type_info().clone(),
+        ));
+    }
+    Ok(query_node)
+}
+
+/// `ReflectDeserializer` produces dynamic values, turn them back into the concrete type if we can.
+pub(crate) fn deserialize_value(value: &serde_json::Value, type_registry: &TypeRegistry) -> Result<Box<dyn Reflect>, GraphFileError> {
+    let value = ReflectDeserializer::new(type_registry)
+        .deserialize(value.clone())
+        .map_err(|err| GraphFileError::Value(err.to_string()))?;
+    Ok(value
+        .get_represented_type_info()
+        .and_then(|type_info| type_registry.get_type_data::<ReflectFromReflect>(type_info.type_id()))
+        .and_then(|from_reflect| from_reflect.from_reflect(value.as_ref()))
+        .unwrap_or(value))
+}
+
+/// Returns what was written.
+pub fn save_graph(path: impl AsRef<Path>, snarl: &Snarl<ScriptNode>, type_registry: &TypeRegistry) -> Result<GraphFile, GraphFileError> {
+    let graph_file = GraphFile::from_snarl(snarl, type_registry)?;
+    if let Some(parent) = path.as_ref().parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, serde_json::to_string_pretty(&graph_file)?)?;
+    Ok(graph_file)
+}
+
+pub fn load_graph(
+    path: impl AsRef<Path>,
+    function_registry: &FunctionRegistry,
+    type_registry: &TypeRegistry,
+    component_map: &ComponentMap,
+) -> Result<Snarl<ScriptNode>, GraphFileError> {
+    let graph_file: GraphFile = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+    graph_file.to_snarl(function_registry, type_registry, component_map)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::hot_reload::{check_call_sites, migrate_call_site, CallSiteProblem};
+    use crate::registry::FunctionDescriptor;
+    use super::*;
+
+    fn sub(a: f32, b: f32) -> f32 {
+        a - b
+    }
+
+    fn node(id: usize, kind: SavedNodeKind) -> SavedNode {
+        SavedNode {
+            id,
+            pos: [0.0, 0.0],
+            kind,
+        }
+    }
+
+    fn call_sub() -> SavedNodeKind {
+        SavedNodeKind::Function {
+            id: "sub".to_string(),
+            version: 0,
+            overload: None,
+            signature: None,
+        }
+    }
+
+    fn wire(from: (usize, usize), to: (usize, usize)) -> SavedWire {
+        SavedWire { from, to }
+    }
+
+    /// `self` flowing into two `sub` calls, the second one taking the first's result.
+    fn two_calls() -> GraphFile {
+        GraphFile {
+            version: GRAPH_FILE_VERSION,
+            nodes: vec![
+                node(0, SavedNodeKind::SelfEntity { components: vec![] }),
+                node(1, call_sub()),
+                node(2, call_sub()),
+            ],
+            wires: vec![wire((0, 0), (1, 0)), wire((1, 0), (2, 0)), wire((1, 1), (2, 2))],
+        }
+    }
+
+    #[test]
+    fn unregistered_functions_load_as_placeholders_and_migrate() {
+        let type_registry = TypeRegistry::default();
+        let mut function_registry = FunctionRegistry::default();
+        let mut snarl = two_calls()
+            .to_snarl(&function_registry, &type_registry, &ComponentMap::default())
+            .unwrap();
+        assert_eq!(snarl.wires().count(), 3);
+        let broken = check_call_sites(&snarl, &function_registry);
+        assert_eq!(broken.len(), 2);
+        assert!(broken.iter().all(|broken| matches!(broken.problem, CallSiteProblem::Unregistered)));
+
+        function_registry.register("sub", sub, FunctionDescriptor::new());
+        assert!(check_call_sites(&snarl, &function_registry)
+            .iter()
+            .all(|broken| matches!(broken.problem, CallSiteProblem::Unresolved)));
+        let nodes = snarl.node_ids().map(|(id, _)| id).collect::<Vec<_>>();
+        for node in nodes {
+            migrate_call_site(&mut snarl, node, &function_registry, &type_registry);
+        }
+        assert!(check_call_sites(&snarl, &function_registry).is_empty());
+        assert_eq!(snarl.wires().count(), 3);
+    }
+
+    #[test]
+    fn wires_into_missing_pins_are_dropped() {
+        let type_registry = TypeRegistry::default();
+        let mut function_registry = FunctionRegistry::default();
+        function_registry.register("sub", sub, FunctionDescriptor::new());
+        let mut graph_file = two_calls();
+        // saved while `sub` took a third argument
+        graph_file.wires.push(wire((1, 1), (2, 3)));
+        let snarl = graph_file
+            .to_snarl(&function_registry, &type_registry, &ComponentMap::default())
+            .unwrap();
+        assert_eq!(snarl.wires().count(), 3);
+    }
+}
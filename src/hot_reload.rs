@@ -0,0 +1,250 @@
+use bevy::prelude::*;
+use bevy::reflect::func::FunctionInfo;
+use bevy::reflect::TypeRegistry;
+use egui_snarl::{InPinId, NodeId, OutPinId, Snarl};
+use crate::graph_file::SavedSignature;
+use crate::registry::FunctionRegistry;
+use crate::scripting::{FunctionNode, FunctionReturn, ScriptNode, UnresolvedNode};
+use crate::SnarlResource;
+
+/// Tracks whether the graph has to be recompiled because functions it calls changed.
+#[derive(Resource, Default)]
+pub struct ScriptStatus {
+    pub needs_recompile: bool,
+    pub broken_call_sites: Vec<BrokenCallSite>,
+}
+
+#[derive(Clone, Debug)]
+pub struct BrokenCallSite {
+    pub node: NodeId,
+    pub function: String,
+    pub problem: CallSiteProblem,
+}
+
+#[derive(Clone, Debug)]
+pub enum CallSiteProblem {
+    Unregistered,
+    /// The function was registered after the graph was loaded, the placeholder has to be migrated.
+    Unresolved,
+    SignatureChanged,
+    /// The node was made from an older version of the function.
+    Outdated {
+        graph_version: u32,
+        version: u32,
+    },
+}
+
+impl std::fmt::Display for CallSiteProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CallSiteProblem::Unregistered => write!(f, "is no longer registered"),
+            CallSiteProblem::Unresolved => write!(f, "wasn't registered when the graph was loaded"),
+            CallSiteProblem::SignatureChanged => write!(f, "changed its signature"),
+            CallSiteProblem::Outdated { graph_version, version } => write!(
+                f,
+                "is at version {}, the node was made from version {}",
+                version, graph_version
+            ),
+        }
+    }
+}
+
+impl std::fmt::Display for BrokenCallSite {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "node {:?}: `{}` {}", self.node, self.function, self.problem)
+    }
+}
+
+/// Marks the graph for recompilation when a function it calls was registered, replaced or
+/// unregistered, and collects the call sites that no longer fit the registry.
+pub fn detect_function_changes(
+    mut function_registry: ResMut<FunctionRegistry>,
+    snarl: Res<SnarlResource>,
+    mut status: ResMut<ScriptStatus>,
+) {
+    // taking the set through `DerefMut` every frame would mark the registry as changed every frame
+    if !function_registry.has_changed() {
+        return;
+    }
+    let changed = function_registry.bypass_change_detection().take_changed();
+    let uses_changed = snarl.0.node_ids().any(|(_, node)| match node {
+        ScriptNode::Function(function_node) => {
+            changed.contains(&function_node_name(function_node))
+                || function_registry
+                    .resolve(function_node.function_id())
+                    .is_some_and(|name| changed.contains(name))
+        }
+        ScriptNode::Unresolved(unresolved) => changed.contains(unresolved.name()),
+        _ => false,
+    });
+    if uses_changed {
+        status.needs_recompile = true;
+    }
+    status.broken_call_sites = check_call_sites(&snarl.0, &function_registry);
+    for broken in &status.broken_call_sites {
+        println!("broken call site: {}", broken);
+    }
+}
+
+fn function_node_name(function_node: &FunctionNode) -> String {
+    match &function_node.overload {
+        Some(overload) => overload.clone(),
+        None => function_node.function_info.name().unwrap_or("").to_string(),
+    }
+}
+
+pub fn check_call_sites(snarl: &Snarl<ScriptNode>, function_registry: &FunctionRegistry) -> Vec<BrokenCallSite> {
+    let mut broken = vec![];
+    for (node, script_node) in snarl.node_ids() {
+        match script_node {
+            ScriptNode::Function(function_node) => {
+                if let Some(problem) = call_site_problem(function_node, function_registry) {
+                    broken.push(BrokenCallSite {
+                        node,
+                        function: function_node_name(function_node),
+                        problem,
+                    });
+                }
+            }
+            ScriptNode::Unresolved(unresolved) => broken.push(BrokenCallSite {
+                node,
+                function: unresolved.name().to_string(),
+                problem: unresolved_problem(unresolved, function_registry),
+            }),
+            _ => {}
+        }
+    }
+    broken
+}
+
+/// A placeholder is always broken, even once its function is registered again.
+pub fn unresolved_problem(unresolved: &UnresolvedNode, function_registry: &FunctionRegistry) -> CallSiteProblem {
+    if registered_name(unresolved.overload.as_deref(), &unresolved.id, function_registry).is_some() {
+        CallSiteProblem::Unresolved
+    } else {
+        CallSiteProblem::Unregistered
+    }
+}
+
+/// The function a node migrates to, the first member stands in for an overload set.
+fn registered_name(overload: Option<&str>, id: &str, function_registry: &FunctionRegistry) -> Option<String> {
+    match overload {
+        Some(overload) => function_registry.overloads.get(overload).map(|members| members[0].clone()),
+        None => function_registry.resolve(id).map(|a| a.to_string()),
+    }
+}
+
+/// Compares a node against the function registered under its id, renames through an alias are fine.
+pub fn call_site_problem(function_node: &FunctionNode, function_registry: &FunctionRegistry) -> Option<CallSiteProblem> {
+    if let Some(overload) = &function_node.overload {
+        let Some(members) = function_registry.overloads.get(overload) else {
+            return Some(CallSiteProblem::Unregistered);
+        };
+        let arg_count = function_registry.functions[&members[0]].signature().arg_count();
+        return (arg_count != function_node.function_info.arg_count()).then_some(CallSiteProblem::SignatureChanged);
+    }
+    let Some(name) = function_registry.resolve(function_node.function_id()) else {
+        return Some(CallSiteProblem::Unregistered);
+    };
+    let registered = &function_registry.functions[name];
+    if registered.descriptor.version != function_node.descriptor.version {
+        return Some(CallSiteProblem::Outdated {
+            graph_version: function_node.descriptor.version,
+            version: registered.descriptor.version,
+        });
+    }
+    // nodes loaded from a file were rebuilt from the registry, only the saved signature can tell
+    let saved_changed = function_node
+        .saved_signature
+        .as_ref()
+        .is_some_and(|saved| *saved != SavedSignature::of(registered.signature()));
+    (saved_changed || !same_signature(registered.signature(), &function_node.function_info))
+        .then_some(CallSiteProblem::SignatureChanged)
+}
+
+fn same_signature(a: &FunctionInfo, b: &FunctionInfo) -> bool {
+    a.arg_count() == b.arg_count()
+        && a.return_info().type_path() == b.return_info().type_path()
+        && a.args().iter().zip(b.args()).all(|(a, b)| {
+            a.index() == b.index()
+                && a.type_path() == b.type_path()
+                && a.ownership() == b.ownership()
+        })
+}
+
+/// Rebuilds a function node from the function currently registered under its id.
+///
+/// Wires into parameters that still exist under the same name are kept, as are wires out of
+/// returns whose type didn't change, everything else is disconnected. An [`UnresolvedNode`]
+/// doesn't know what its pins were, so its wires are kept by pin index where the pin still
+/// exists. Returns `false` if there is nothing to migrate to.
+pub fn migrate_call_site(
+    snarl: &mut Snarl<ScriptNode>,
+    node: NodeId,
+    function_registry: &FunctionRegistry,
+    type_registry: &TypeRegistry,
+) -> bool {
+    let Some(old) = snarl.get_node(node).cloned() else {
+        return false;
+    };
+    let (overload, id) = match &old {
+        ScriptNode::Function(old) => (old.overload.clone(), old.function_id().to_string()),
+        ScriptNode::Unresolved(old) => (old.overload.clone(), old.id.clone()),
+        _ => return false,
+    };
+    let Some(name) = registered_name(overload.as_deref(), &id, function_registry) else {
+        return false;
+    };
+    let registered = &function_registry.functions[&name];
+    let function_info = registered.info().with_name(overload.clone().unwrap_or(name));
+    let returns = FunctionReturn::from_info(&function_info, type_registry);
+    let mut new = FunctionNode::new(function_info, returns, registered.descriptor.clone());
+    new.overload = overload;
+
+    let wires = snarl
+        .wires()
+        .filter(|(out_pin, in_pin)| out_pin.node == node || in_pin.node == node)
+        .collect::<Vec<_>>();
+    for (out_pin, in_pin) in &wires {
+        snarl.disconnect(*out_pin, *in_pin);
+    }
+    snarl[node] = ScriptNode::Function(new.clone());
+    for (mut out_pin, mut in_pin) in wires {
+        if in_pin.node == node {
+            let input = match &old {
+                ScriptNode::Unresolved(_) => (in_pin.input < new.input_pin_count()).then_some(in_pin.input),
+                ScriptNode::Function(old) if old.is_flow_input(in_pin.input) => (!new.descriptor.pure).then_some(0),
+                ScriptNode::Function(old) => old.arg_at_pin(in_pin.input).and_then(|old_arg| {
+                    let arg_name = FunctionNode::arg_name(old_arg);
+                    new.args()
+                        .into_iter()
+                        .find(|arg| FunctionNode::arg_name(arg) == arg_name)
+                        .map(|arg| new.input_pin(arg))
+                }),
+                _ => unreachable!(),
+            };
+            let Some(input) = input else {
+                continue;
+            };
+            in_pin = InPinId { node, input };
+        }
+        if out_pin.node == node {
+            let output = match &old {
+                ScriptNode::Unresolved(_) => (out_pin.output < new.output_pin_count()).then_some(out_pin.output),
+                ScriptNode::Function(old) if old.is_flow_output(out_pin.output) => (!new.descriptor.pure).then_some(0),
+                ScriptNode::Function(old) => {
+                    let index = out_pin.output - old.output_pin(0);
+                    (old.return_at_pin(out_pin.output) == new.returns.types().get(index).copied())
+                        .then(|| new.output_pin(index))
+                }
+                _ => unreachable!(),
+            };
+            let Some(output) = output else {
+                continue;
+            };
+            out_pin = OutPinId { node, output };
+        }
+        snarl.connect(out_pin, in_pin);
+    }
+    true
+}
@@ -1,120 +1,377 @@
-use std::collections::{HashMap, HashSet};
-use bevy::reflect::{Reflect, ReflectMut, ReflectRef};
+use std::cell::{Ref, RefCell, RefMut};
+use std::fmt::{Debug, Display, Formatter};
+use bevy::ecs::component::ComponentId;
+use bevy::ecs::world::unsafe_world_cell::UnsafeWorldCell;
+use bevy::prelude::Entity;
+use bevy::reflect::{Reflect, ReflectFromPtr, ReflectMut, ReflectRef};
 
-#[derive(Debug, Default)]
-pub struct IndirectStack<'a> {
-    values: Vec<StackValue<'a>>,
-    field_mapping: HashMap<usize, (String, usize)>,
-}
+/// A handle to a slot on the [`IndirectStack`].
+///
+/// Handles never hand out references by themselves, every access goes through
+/// [`IndirectStack::get`] or [`IndirectStack::get_mut`] which check the borrow at runtime.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct StackHandle(pub usize);
 
-#[derive(Debug)]
-pub enum StackValue<'a> {
+pub enum StackValue {
     Owned(Box<dyn Reflect>),
-    Mut(&'a mut dyn Reflect),
+    /// A component living in the world, resolved every time the slot is borrowed.
+    Component {
+        entity: Entity,
+        component_id: ComponentId,
+        from_ptr: ReflectFromPtr,
+    },
     InternalReference {
         name: String,
-        parent: usize,
+        parent: StackHandle,
+    },
+    /// A borrow of another slot, only resolved when it is passed to a function.
+    Ref(StackHandle),
+    Mut(StackHandle),
+}
+
+impl Clone for StackValue {
+    fn clone(&self) -> Self {
+        match self {
+            StackValue::Owned(owned) => StackValue::Owned(owned.clone_value()),
+            StackValue::Component { entity, component_id, from_ptr } => StackValue::Component {
+                entity: *entity,
+                component_id: *component_id,
+                from_ptr: from_ptr.clone(),
+            },
+            StackValue::InternalReference { name, parent } => StackValue::InternalReference {
+                name: name.clone(),
+                parent: *parent,
+            },
+            StackValue::Ref(target) => StackValue::Ref(*target),
+            StackValue::Mut(target) => StackValue::Mut(*target),
+        }
+    }
+}
+
+impl Debug for StackValue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StackValue::Owned(owned) => f.debug_tuple("Owned").field(owned).finish(),
+            StackValue::Component { entity, component_id, .. } => f
+                .debug_struct("Component")
+                .field("entity", entity)
+                .field("component_id", component_id)
+                .finish(),
+            StackValue::InternalReference { name, parent } => f
+                .debug_struct("InternalReference")
+                .field("name", name)
+                .field("parent", parent)
+                .finish(),
+            StackValue::Ref(target) => f.debug_tuple("Ref").field(target).finish(),
+            StackValue::Mut(target) => f.debug_tuple("Mut").field(target).finish(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum StackError {
+    InvalidHandle(StackHandle),
+    /// The slot (or the slot its field lives in) is already borrowed in a conflicting way.
+    AlreadyBorrowed(StackHandle),
+    DuplicateComponent {
+        entity: Entity,
+        component_id: ComponentId,
+    },
+    MissingComponent {
+        entity: Entity,
+        component_id: ComponentId,
+    },
+    MissingField {
+        parent: StackHandle,
+        name: String,
+    },
+    /// A value that can't be pushed this way, e.g. a component through [`IndirectStack::push`].
+    InvalidValue(String),
+}
+
+impl Display for StackError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StackError::InvalidHandle(handle) => write!(f, "stack slot {} does not exist", handle.0),
+            StackError::AlreadyBorrowed(handle) => write!(f, "stack slot {} is already borrowed", handle.0),
+            StackError::DuplicateComponent { entity, component_id } => write!(f, "component {:?} of entity {} is already on the stack", component_id, entity),
+            StackError::MissingComponent { entity, component_id } => write!(f, "entity {} has no component {:?}", entity, component_id),
+            StackError::MissingField { parent, name } => write!(f, "stack slot {} has no field `{}`", parent.0, name),
+            StackError::InvalidValue(message) => write!(f, "{}", message),
+        }
     }
 }
 
-impl<'a> IndirectStack<'a> {
+impl std::error::Error for StackError {}
+
+/// The value stack of the virtual machine.
+///
+/// Every slot sits behind a [`RefCell`], borrowing a field borrows the slot it lives in, so
+/// aliasing a value mutably results in [`StackError::AlreadyBorrowed`] instead of two `&mut`.
+pub struct IndirectStack<'w> {
+    world: UnsafeWorldCell<'w>,
+    values: Vec<RefCell<StackValue>>,
+}
 
-    pub fn push(&mut self, stack_value: StackValue<'a>) {
-        self.values.push(stack_value)
+impl<'w> IndirectStack<'w> {
+    /// # Safety
+    ///
+    /// The stack hands out references to the components pushed onto it, so `world` must not be
+    /// accessed by anything else while the stack is alive.
+    pub unsafe fn new(world: UnsafeWorldCell<'w>) -> Self {
+        Self {
+            world,
+            values: vec![],
+        }
     }
 
-    pub fn push_owned(&mut self, owned: Box<dyn Reflect>) {
-        self.values.push(StackValue::Owned(owned))
+    /// Components can only be pushed through [`IndirectStack::push_component`], references have
+    /// to point below the slot they are pushed to.
+    pub fn push(&mut self, stack_value: StackValue) -> Result<StackHandle, StackError> {
+        match &stack_value {
+            StackValue::Owned(_) => {}
+            StackValue::Component { .. } => {
+                return Err(StackError::InvalidValue("components have to be pushed with `push_component`".to_string()));
+            }
+            StackValue::InternalReference { parent: target, .. } | StackValue::Ref(target) | StackValue::Mut(target) => {
+                // following a reference to itself (or above) would never end
+                if target.0 >= self.values.len() {
+                    return Err(StackError::InvalidHandle(*target));
+                }
+            }
+        }
+        self.values.push(RefCell::new(stack_value));
+        Ok(StackHandle(self.values.len() - 1))
     }
-    pub fn push_internal_ref(&mut self, name: String, parent: usize) {
-        self.values.push(StackValue::InternalReference {
+
+    pub fn push_owned(&mut self, owned: Box<dyn Reflect>) -> StackHandle {
+        self.values.push(RefCell::new(StackValue::Owned(owned)));
+        StackHandle(self.values.len() - 1)
+    }
+
+    pub fn push_internal_ref(&mut self, name: String, parent: StackHandle) -> Result<StackHandle, StackError> {
+        self.push(StackValue::InternalReference {
             name,
             parent,
-        });
+        })
+    }
+
+    pub fn push_ref(&mut self, target: StackHandle) -> Result<StackHandle, StackError> {
+        self.push(StackValue::Ref(target))
+    }
+
+    pub fn push_mut(&mut self, target: StackHandle) -> Result<StackHandle, StackError> {
+        self.push(StackValue::Mut(target))
     }
-    pub fn push_mut(&mut self, r#mut: &'a mut dyn Reflect) {
-        self.values.push(StackValue::Mut(r#mut))
+
+    /// # Safety
+    ///
+    /// `from_ptr` has to be the [`ReflectFromPtr`] of the type `component_id` stands for, the
+    /// stack casts the component's pointer with it whenever the slot is borrowed.
+    pub unsafe fn push_component(&mut self, entity: Entity, component_id: ComponentId, from_ptr: ReflectFromPtr) -> Result<StackHandle, StackError> {
+        let duplicate = self.values.iter().any(|value| matches!(
+            &*value.borrow(),
+            StackValue::Component { entity: e, component_id: c, .. } if *e == entity && *c == component_id
+        ));
+        if duplicate {
+            return Err(StackError::DuplicateComponent {
+                entity,
+                component_id,
+            });
+        }
+        self.values.push(RefCell::new(StackValue::Component {
+            entity,
+            component_id,
+            from_ptr,
+        }));
+        Ok(StackHandle(self.values.len() - 1))
     }
 
     pub fn len(&self) -> usize {
         self.values.len()
     }
 
-    pub fn pop(&mut self) -> Option<StackValue<'a>> {
-        self.values.pop()
+    pub fn pop(&mut self) -> Option<StackValue> {
+        self.values.pop().map(RefCell::into_inner)
     }
 
-    pub unsafe fn get_internal_from_ref(&self, parent: usize, name: String) -> Option<&'a dyn Reflect> {
-        let mut thing = self.get_ref_internal(parent)?;
-        let thing = match thing.reflect_ref() {
-            ReflectRef::Struct(dyn_struct) => {
-                dyn_struct.field(&name)?
+    pub fn get(&self, handle: StackHandle) -> Result<Ref<'_, dyn Reflect>, StackError> {
+        let slot = self.values.get(handle.0).ok_or(StackError::InvalidHandle(handle))?;
+        let value = slot.try_borrow().map_err(|_| StackError::AlreadyBorrowed(handle))?;
+        if let StackValue::InternalReference { name, parent } = &*value {
+            let (name, parent) = (name.clone(), *parent);
+            drop(value);
+            return self.get_field(parent, name);
+        }
+        if let StackValue::Ref(target) | StackValue::Mut(target) = &*value {
+            let target = *target;
+            drop(value);
+            return self.get(target);
+        }
+        let world = self.world;
+        Ref::filter_map(value, |value| match value {
+            StackValue::Owned(owned) => Some(owned.as_ref()),
+            StackValue::Component { entity, component_id, from_ptr } => {
+                // SAFETY: components are only reachable through their slot and a component can only
+                // be pushed once, so the shared borrow of the slot rules out a live `&mut`.
+                // `from_ptr` belongs to the component's type as required by `IndirectStack::new`.
+                let ptr = unsafe { world.get_entity(*entity)?.get_by_id(*component_id)? };
+                Some(unsafe { from_ptr.as_reflect(ptr) })
             }
-            _ => todo!(),
-        };
-        let thing = thing as *const dyn Reflect;
-        Some(unsafe { &*thing})
+            StackValue::InternalReference { .. } | StackValue::Ref(_) | StackValue::Mut(_) => unreachable!(),
+        })
+        .map_err(|value| missing_component(&value))
     }
 
-    pub unsafe fn get_mut_internal_from_ref(&mut self, parent: usize, name: String) -> Option<&'a mut dyn Reflect>{
-        let mut thing = self.get_mut_internal(parent)?;
-        let thing = match thing.reflect_mut() {
-            ReflectMut::Struct(dyn_struct) => {
-                dyn_struct.field_mut(&name)?
-            }
-            _ => todo!(),
-        };
-        let thing = thing as *mut dyn Reflect;
-        Some(unsafe { &mut *thing})
-    }
-
-    pub unsafe fn get_ref_internal(&self, index: usize) -> Option<&'a dyn Reflect> {
-        let is_real = match self.values.get(index)? {
-            StackValue::Owned(_) => true,
-            StackValue::Mut(_) => true,
-            StackValue::InternalReference { .. } => false,
-        };
-
-        if is_real {
-            let thing = match self.values.get(index)? {
-                StackValue::Owned(ref dyn_reflect) => { dyn_reflect.as_ref() },
-                StackValue::Mut(dyn_reflect) => {dyn_reflect.as_reflect()}
-                StackValue::InternalReference { .. } => unreachable!(),
-            } as *const dyn Reflect;
-
-            let thing = unsafe { &*thing};
-            Some(thing)
-        } else {
-            let (name, parent) = match self.values.get(index).unwrap() {
-                StackValue::InternalReference { name, parent} => (name.clone(), *parent),
-                _ => unreachable!(),
-            };
-            self.get_internal_from_ref(parent, name)
+    pub fn get_mut(&self, handle: StackHandle) -> Result<RefMut<'_, dyn Reflect>, StackError> {
+        let slot = self.values.get(handle.0).ok_or(StackError::InvalidHandle(handle))?;
+        let value = slot.try_borrow_mut().map_err(|_| StackError::AlreadyBorrowed(handle))?;
+        if let StackValue::InternalReference { name, parent } = &*value {
+            let (name, parent) = (name.clone(), *parent);
+            drop(value);
+            return self.get_field_mut(parent, name);
+        }
+        if let StackValue::Mut(target) = &*value {
+            let target = *target;
+            drop(value);
+            return self.get_mut(target);
         }
+        if let StackValue::Ref(_) = &*value {
+            return Err(StackError::AlreadyBorrowed(handle));
+        }
+        let world = self.world;
+        RefMut::filter_map(value, |value| match value {
+            StackValue::Owned(owned) => Some(owned.as_mut()),
+            StackValue::Component { entity, component_id, from_ptr } => {
+                // SAFETY: the exclusive borrow of the slot is the only path to this component.
+                let ptr = unsafe { world.get_entity(*entity)?.get_mut_by_id(*component_id)? };
+                Some(unsafe { from_ptr.as_reflect_mut(ptr.into_inner()) })
+            }
+            StackValue::InternalReference { .. } | StackValue::Ref(_) | StackValue::Mut(_) => unreachable!(),
+        })
+        .map_err(|value| missing_component(&value))
+    }
+
+    pub fn get_field(&self, parent: StackHandle, name: String) -> Result<Ref<'_, dyn Reflect>, StackError> {
+        Ref::filter_map(self.get(parent)?, |value| match value.reflect_ref() {
+            ReflectRef::Struct(dyn_struct) => dyn_struct.field(&name),
+            ReflectRef::Tuple(dyn_tuple) => dyn_tuple.field(name.parse().ok()?),
+            ReflectRef::TupleStruct(dyn_tuple_struct) => dyn_tuple_struct.field(name.parse().ok()?),
+            _ => None,
+        })
+        .map_err(|_| StackError::MissingField { parent, name })
+    }
+
+    pub fn get_field_mut(&self, parent: StackHandle, name: String) -> Result<RefMut<'_, dyn Reflect>, StackError> {
+        RefMut::filter_map(self.get_mut(parent)?, |value| match value.reflect_mut() {
+            ReflectMut::Struct(dyn_struct) => dyn_struct.field_mut(&name),
+            ReflectMut::Tuple(dyn_tuple) => dyn_tuple.field_mut(name.parse().ok()?),
+            ReflectMut::TupleStruct(dyn_tuple_struct) => dyn_tuple_struct.field_mut(name.parse().ok()?),
+            _ => None,
+        })
+        .map_err(|_| StackError::MissingField { parent, name })
+    }
+}
+
+fn missing_component(value: &StackValue) -> StackError {
+    match value {
+        StackValue::Component { entity, component_id, .. } => StackError::MissingComponent {
+            entity: *entity,
+            component_id: *component_id,
+        },
+        _ => unreachable!(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::World;
+    use bevy::reflect::func::{Arg, ArgList, IntoFunction};
+    use bevy::reflect::{GetTypeRegistration, ReflectFromPtr};
+    use crate::test_support::Position;
+    use super::*;
+
+    fn scale(value: &mut f32, factor: &f32) {
+        *value *= *factor;
+    }
+
+    fn spawn_position(world: &mut World) -> (Entity, ComponentId, ReflectFromPtr) {
+        let entity = world.spawn(Position { x: 1.0, y: 2.0 }).id();
+        let component_id = world.component_id::<Position>().unwrap();
+        let from_ptr = Position::get_type_registration().data::<ReflectFromPtr>().unwrap().clone();
+        (entity, component_id, from_ptr)
+    }
+
+    #[test]
+    fn component_is_pushed_once() {
+        let mut world = World::new();
+        let (entity, component_id, from_ptr) = spawn_position(&mut world);
+        // SAFETY: the world isn't touched while the stack is alive and `from_ptr` is `Position`'s.
+        let mut stack = unsafe { IndirectStack::new(world.as_unsafe_world_cell()) };
+        unsafe { stack.push_component(entity, component_id, from_ptr.clone()) }.unwrap();
+        assert!(matches!(
+            unsafe { stack.push_component(entity, component_id, from_ptr.clone()) },
+            Err(StackError::DuplicateComponent { .. })
+        ));
+        // the safe path can't plant a component with an unchecked `from_ptr`
+        assert!(matches!(
+            stack.push(StackValue::Component { entity, component_id, from_ptr }),
+            Err(StackError::InvalidValue(_))
+        ));
     }
 
-    pub unsafe fn get_mut_internal(&mut self, index: usize) -> Option<&'a mut dyn Reflect> {
-        let is_real = match self.values.get(index)? {
-            StackValue::Owned(_) => true,
-            StackValue::Mut(_) => true,
-            StackValue::InternalReference { .. } => false,
-        };
-
-        if is_real {
-            let thing = match self.values.get_mut(index)? {
-                StackValue::Owned(ref mut dyn_reflect) => { dyn_reflect.as_mut() },
-                StackValue::Mut(dyn_reflect) => {dyn_reflect.as_reflect_mut()}
-                StackValue::InternalReference { .. } => unreachable!(),
-            } as *mut dyn Reflect;
-
-            let thing = unsafe { &mut *thing};
-            Some(thing)
-        } else {
-            let (name, parent) = match self.values.get(index).unwrap() {
-                StackValue::InternalReference { name, parent} => (name.clone(), *parent),
-                _ => unreachable!(),
-            };
-            self.get_mut_internal_from_ref(parent, name)
+    #[test]
+    fn references_have_to_point_below_themselves() {
+        let mut world = World::new();
+        // SAFETY: the world isn't touched while the stack is alive.
+        let mut stack = unsafe { IndirectStack::new(world.as_unsafe_world_cell()) };
+        let value = stack.push_owned(Box::new(1.0_f32));
+        assert!(matches!(stack.push_ref(StackHandle(1)), Err(StackError::InvalidHandle(_))));
+        assert!(matches!(stack.push_mut(StackHandle(2)), Err(StackError::InvalidHandle(_))));
+        assert!(matches!(stack.push_internal_ref("x".to_string(), StackHandle(1)), Err(StackError::InvalidHandle(_))));
+        assert!(stack.push_ref(value).is_ok());
+    }
+
+    #[test]
+    fn field_is_not_mutable_while_parent_is_borrowed() {
+        let mut world = World::new();
+        let (entity, component_id, from_ptr) = spawn_position(&mut world);
+        // SAFETY: the world isn't touched while the stack is alive and `from_ptr` is `Position`'s.
+        let mut stack = unsafe { IndirectStack::new(world.as_unsafe_world_cell()) };
+        let position = unsafe { stack.push_component(entity, component_id, from_ptr) }.unwrap();
+        let x = stack.push_internal_ref("x".to_string(), position).unwrap();
+        let shared = stack.get(position).unwrap();
+        assert!(matches!(stack.get_mut(x), Err(StackError::AlreadyBorrowed(handle)) if handle == position));
+        drop(shared);
+        assert!(stack.get_mut(x).is_ok());
+    }
+
+    #[test]
+    fn ref_and_mut_arguments_reach_the_component() {
+        let mut world = World::new();
+        let (entity, component_id, from_ptr) = spawn_position(&mut world);
+        {
+            // SAFETY: the world isn't touched while the stack is alive and `from_ptr` is `Position`'s.
+            let mut stack = unsafe { IndirectStack::new(world.as_unsafe_world_cell()) };
+            let position = unsafe { stack.push_component(entity, component_id, from_ptr) }.unwrap();
+            let x = stack.push_internal_ref("x".to_string(), position).unwrap();
+            let factor = stack.push_owned(Box::new(3.0_f32));
+            let value = stack.push_mut(x).unwrap();
+            let factor = stack.push_ref(factor).unwrap();
+
+            // a shared borrow of the same slot conflicts with the `&mut` argument
+            let alias = stack.push_ref(x).unwrap();
+            let alias = stack.get(alias).unwrap();
+            assert!(matches!(stack.get_mut(value), Err(StackError::AlreadyBorrowed(_))));
+            drop(alias);
+
+            let mut value = stack.get_mut(value).unwrap();
+            let factor = stack.get(factor).unwrap();
+            let mut function = scale.into_function();
+            function
+                .call(ArgList::new().push(Arg::Mut(&mut *value)).push(Arg::Ref(&*factor)))
+                .unwrap();
         }
+        assert_eq!(world.get::<Position>(entity).unwrap().x, 3.0);
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,413 @@
+pub mod registry;
+pub mod scripting;
+pub mod compiler;
+pub mod virtual_machine;
+pub mod indirect_stack;
+pub mod methods;
+pub mod hot_reload;
+pub mod schema;
+pub mod graph_file;
+pub mod graph_asset;
+pub mod script_component;
+pub mod clipboard;
+pub mod codegen;
+pub mod text_script;
+pub mod graph_export;
+pub mod program;
+#[cfg(test)]
+mod test_support;
+
+// `#[script_function]` expands to `::bevy_lek_scripting` paths, also inside this crate
+extern crate self as bevy_lek_scripting;
+
+pub use bevy_lek_scripting_macros::{module_fns, script_function};
+
+use crate::clipboard::NodeSelection;
+use crate::graph_asset::{GraphAssetPlugin, SavedMainGraph};
+use crate::script_component::{CompiledScripts, Script, ScriptComponentPlugin, ScriptParameters};
+use crate::hot_reload::{check_call_sites, detect_function_changes, migrate_call_site, ScriptStatus};
+use crate::methods::RegisterMethods;
+use crate::registry::{ComponentMap, FunctionDescriptor, FunctionRegistry, MainThreadFunctions, RegisterFunction, RegisterScriptFunctions, RegisterWorldFunction, RegistryPlugin};
+use bevy::ecs::component::{ComponentId, Components};
+use bevy::prelude::*;
+use bevy::reflect::func::{ArgList, Function, FunctionInfo, IntoFunction, Return};
+use bevy::reflect::{ReflectMut, TypeInfo, TypeRegistry, TypeRegistryArc};
+use bevy_egui::egui::{emath, Color32, Pos2, ScrollArea, Ui};
+use bevy_egui::{egui, EguiContexts, EguiPlugin};
+use bevy_inspector_egui::bevy_inspector::short_circuit;
+use bevy_inspector_egui::inspector_egui_impls::InspectorEguiImpl;
+use bevy_inspector_egui::reflect_inspector::InspectorUi;
+use bevy_inspector_egui::DefaultInspectorConfigPlugin;
+use egui_snarl::ui::{PinInfo, SnarlStyle, SnarlViewer};
+use egui_snarl::{InPin, NodeId, OutPin, Snarl};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::any::Any;
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+use bevy::ecs::system::SystemId;
+use crate::virtual_machine::{run, uses_main_thread};
+/*use crate::virtual_machine::run;*/
+
+const GRAPH_PATH: &str = "assets/main.lekgraph";
+const TEXT_SCRIPT_PATH: &str = "assets/main.lek";
+const PROGRAM_PATH: &str = "assets/main.lekprogram";
+
+const STRING_COLOR: Color32 = Color32::from_rgb(0x00, 0xb0, 0x00);
+const NUMBER_COLOR: Color32 = Color32::from_rgb(0xb0, 0x00, 0x00);
+const IMAGE_COLOR: Color32 = Color32::from_rgb(0xb0, 0x00, 0xb0);
+const UNTYPED_COLOR: Color32 = Color32::from_rgb(0xb0, 0xb0, 0xb0);
+
+/// The graph editor with its example entities, needs `DefaultPlugins` and [`RegistryPlugin`].
+pub struct EditorPlugin;
+
+impl Plugin for EditorPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins((DefaultInspectorConfigPlugin, EguiPlugin, GraphAssetPlugin, ScriptComponentPlugin));
+        app.init_resource::<ScriptStatus>();
+        app.init_resource::<NodeSelection>();
+        app.insert_resource(SnarlResource::default());
+        app.add_systems(Update, (detect_function_changes, show_egui, print_transforms).chain());
+        app.add_systems(Startup, add_transforms);
+    }
+}
+
+/// The functions and methods graphs can call, shared by the editor and `lek-run`.
+pub struct BuiltinFunctionsPlugin;
+
+impl Plugin for BuiltinFunctionsPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_overload(
+            "add",
+            "add_i32",
+            add_i32,
+            FunctionDescriptor::new()
+                .with_category("Math")
+                .with_description("Adds two integers.")
+                .with_param("a", "the first summand")
+                .with_param("b", "added to `a`")
+                .pure(),
+        );
+        app.register_overload(
+            "add",
+            "add_f32",
+            add_f32,
+            FunctionDescriptor::new()
+                .with_category("Math")
+                .with_description("Adds two floats.")
+                .with_param("a", "the first summand")
+                .with_param("b", "added to `a`")
+                .pure(),
+        );
+        app.register_script_functions(module_fns![lerp, clamp]);
+        app.register_world_function(
+            "elapsed_seconds",
+            elapsed_seconds,
+            FunctionDescriptor::new()
+                .with_category("Time")
+                .with_description("Seconds since the app started.")
+                .pure(),
+        );
+        app.register_type::<Transform>();
+        app.register_methods::<Transform>();
+        app.register_methods::<Vec3>();
+        app.register_methods::<Quat>();
+    }
+}
+
+static mut SYSTEM_ID: Option<SystemId> = None;
+
+fn add_transforms(mut commands: Commands) {
+    commands.spawn(Transform::from_translation(Vec3::new(3.0, 3.0, 3.0)));
+    commands.spawn(Transform::from_translation(Vec3::new(5.0, 5.0, 5.0)));
+    unsafe {
+        SYSTEM_ID.replace(commands.register_one_shot_system(run_vm_system));
+    }
+}
+
+fn print_transforms(transforms: Query<(Entity, &Transform), Changed<Transform>>) {
+    for (entity, t) in transforms.iter() {
+        println!("entity: {}, translation: {:?}", entity, t.translation);
+    }
+}
+
+fn run_vm_system(world: &mut World) {
+    world.resource_scope(|world, snarl: Mut<SnarlResource>| {
+        world.resource_scope(|world, function_registry: Mut<FunctionRegistry>| {
+            match crate::compiler::compile(&snarl.0, &function_registry) {
+                Ok(instructions) => {
+                    // only scripts calling main-thread functions touch the non-send tier
+                    let mut main_thread = uses_main_thread(&instructions, &function_registry)
+                        .then(|| world.remove_non_send_resource::<MainThreadFunctions>())
+                        .flatten();
+                    if let Err(err) = run(&instructions, &function_registry, main_thread.as_mut(), world) {
+                        println!("script error: {}", err);
+                    }
+                    if let Some(main_thread) = main_thread {
+                        world.insert_non_send_resource(main_thread);
+                    }
+                }
+                Err(err) => println!("compile error: {}", err),
+            }
+        });
+        world.resource_mut::<ScriptStatus>().needs_recompile = false;
+    });
+}
+
+#[derive(Resource, Default)]
+pub struct SnarlResource(pub Snarl<scripting::ScriptNode>);
+
+fn show_egui(
+    mut commands: Commands,
+    mut contexts: EguiContexts,
+    mut snarl: ResMut<SnarlResource>,
+    function_registry: Res<FunctionRegistry>,
+    mut type_registry: ResMut<AppTypeRegistry>,
+    component_map: Res<ComponentMap>,
+    mut script_status: ResMut<ScriptStatus>,
+    mut saved_main_graph: ResMut<SavedMainGraph>,
+    selection: ResMut<NodeSelection>,
+    compiled_scripts: Res<CompiledScripts>,
+    mut scripts: Query<(Entity, &Script, Option<&mut ScriptParameters>)>,
+    transforms: Query<(Entity, &Transform)>
+) {
+    let mut viewer = crate::scripting::Viewer {
+        function_registry: Some(&function_registry),
+        type_registry: Some(type_registry),
+        component_map: Some(component_map),
+        selection: Some(selection),
+    };
+    let style = SnarlStyle::default();
+    bevy_egui::egui::CentralPanel::default().show(contexts.ctx_mut(), |ui| {
+        snarl.0
+            .show(&mut viewer, &style, bevy_egui::egui::Id::new("snarl"), ui);
+    });
+
+    // ctrl+c / ctrl+v on the graph, text fields handle their own
+    let ctx = contexts.ctx_mut();
+    if !ctx.wants_keyboard_input() {
+        for event in ctx.input(|input| input.events.clone()) {
+            match event {
+                egui::Event::Copy if !viewer.selection.as_ref().unwrap().0.is_empty() => {
+                    let type_registry = viewer.type_registry.as_ref().unwrap().read();
+                    match crate::clipboard::copy_selection(&snarl.0, viewer.selection.as_ref().unwrap(), &type_registry) {
+                        Ok(text) => ctx.output_mut(|output| output.copied_text = text),
+                        Err(err) => println!("couldn't copy selection: {}", err),
+                    }
+                }
+                egui::Event::Paste(text) => {
+                    let type_registry = viewer.type_registry.as_ref().unwrap().read();
+                    let pasted = crate::clipboard::paste(
+                        &text,
+                        &mut snarl.0,
+                        viewer.selection.as_mut().unwrap(),
+                        &function_registry,
+                        &type_registry,
+                        viewer.component_map.as_ref().unwrap(),
+                    );
+                    if let Err(err) = pasted {
+                        println!("couldn't paste: {}", err);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    bevy_egui::egui::SidePanel::left("left_panel").show(contexts.ctx_mut(), |ui| {
+        if ui.button("compile and run script").clicked() {
+            commands.run_system(unsafe {
+                SYSTEM_ID.unwrap()
+            });
+        }
+        ui.horizontal(|ui| {
+            if ui.button("save").clicked() {
+                let type_registry = viewer.type_registry.as_ref().unwrap().read();
+                match crate::graph_file::save_graph(GRAPH_PATH, &snarl.0, &type_registry) {
+                    Ok(saved) => {
+                        // the file watcher picks this up, don't reload it
+                        saved_main_graph.0 = Some(saved);
+                        println!("saved {}", GRAPH_PATH);
+                    }
+                    Err(err) => println!("couldn't save graph: {}", err),
+                }
+            }
+            if ui.button("load").clicked() {
+                let type_registry = viewer.type_registry.as_ref().unwrap().read();
+                let component_map = viewer.component_map.as_ref().unwrap();
+                match crate::graph_file::load_graph(GRAPH_PATH, &function_registry, &type_registry, component_map) {
+                    Ok(loaded) => {
+                        snarl.0 = loaded;
+                        script_status.broken_call_sites = check_call_sites(&snarl.0, &function_registry);
+                        script_status.needs_recompile = true;
+                    }
+                    Err(err) => println!("couldn't load graph: {}", err),
+                }
+            }
+        });
+        ui.horizontal(|ui| {
+            if ui.button("save as text").clicked() {
+                let printed = crate::text_script::print_script(&snarl.0)
+                    .map_err(|err| err.to_string())
+                    .and_then(|source| std::fs::write(TEXT_SCRIPT_PATH, source).map_err(|err| err.to_string()));
+                match printed {
+                    Ok(()) => println!("saved {}", TEXT_SCRIPT_PATH),
+                    Err(err) => println!("couldn't save text script: {}", err),
+                }
+            }
+            if ui.button("load text").clicked() {
+                let type_registry = viewer.type_registry.as_ref().unwrap().read();
+                let component_map = viewer.component_map.as_ref().unwrap();
+                let parsed = std::fs::read_to_string(TEXT_SCRIPT_PATH)
+                    .map_err(|err| err.to_string())
+                    .and_then(|source| {
+                        crate::text_script::parse_script(&source, &function_registry, &type_registry, component_map)
+                            .map_err(|err| err.to_string())
+                    });
+                match parsed {
+                    Ok(parsed) => {
+                        snarl.0 = parsed;
+                        script_status.broken_call_sites = check_call_sites(&snarl.0, &function_registry);
+                        script_status.needs_recompile = true;
+                    }
+                    Err(err) => println!("couldn't load text script: {}", err),
+                }
+            }
+        });
+        if ui.button("save compiled program").clicked() {
+            let type_registry = viewer.type_registry.as_ref().unwrap().read();
+            let saved = crate::compiler::compile(&snarl.0, &function_registry)
+                .map_err(|err| err.to_string())
+                .and_then(|program| crate::program::save_program(PROGRAM_PATH, &program, &function_registry, &type_registry).map_err(|err| err.to_string()));
+            match saved {
+                Ok(()) => println!("saved {}", PROGRAM_PATH),
+                Err(err) => println!("couldn't save program: {}", err),
+            }
+        }
+        if ui.button("generate rust").clicked() {
+            let generated = crate::codegen::generate_from_graph(&snarl.0, &function_registry, "generated_system")
+                .map_err(|err| err.to_string())
+                .and_then(|source| std::fs::write("generated_system.rs", source).map_err(|err| err.to_string()));
+            match generated {
+                Ok(()) => println!("wrote generated_system.rs"),
+                Err(err) => println!("couldn't generate rust: {}", err),
+            }
+        }
+        ui.horizontal(|ui| {
+            for format in [crate::graph_export::GraphFormat::Dot, crate::graph_export::GraphFormat::Mermaid] {
+                if ui.button(format!("export .{}", format.extension())).clicked() {
+                    let path = format!("graph.{}", format.extension());
+                    match crate::graph_export::write_graph(&path, &snarl.0, format) {
+                        Ok(()) => println!("wrote {}", path),
+                        Err(err) => println!("couldn't export graph: {}", err),
+                    }
+                }
+            }
+        });
+        if ui.button("export schema").clicked() {
+            let type_registry = viewer.type_registry.as_ref().unwrap().read();
+            let component_map = viewer.component_map.as_ref().unwrap();
+            match crate::schema::write_schema("schema.json", &function_registry, component_map, &type_registry) {
+                Ok(()) => println!("wrote schema.json"),
+                Err(err) => println!("couldn't write schema: {}", err),
+            }
+        }
+        if script_status.needs_recompile {
+            ui.colored_label(egui::Color32::YELLOW, "functions changed, recompile the script");
+        }
+        let mut edited = false;
+        for broken in &script_status.broken_call_sites {
+            ui.horizontal(|ui| {
+                ui.colored_label(egui::Color32::RED, broken.to_string());
+                if ui.button("migrate").clicked() {
+                    edited |= migrate_call_site(&mut snarl.0, broken.node, &function_registry, &viewer.type_registry.as_ref().unwrap().read());
+                }
+                if ui.button("remove").clicked() {
+                    snarl.0.remove_node(broken.node);
+                    edited = true;
+                }
+            });
+        }
+        if edited {
+            script_status.broken_call_sites = check_call_sites(&snarl.0, &function_registry);
+            script_status.needs_recompile = true;
+        }
+        for (entity, script, mut parameters) in scripts.iter_mut() {
+            let Some(program) = compiled_scripts.0.get(&script.0.id()) else {
+                continue;
+            };
+            let type_registry = viewer.type_registry.as_ref().unwrap().read();
+            ui.collapsing(format!("parameters of {}", entity), |ui| {
+                for (name, default) in crate::virtual_machine::parameters(program) {
+                    ui.horizontal(|ui| {
+                        ui.label(name);
+                        match parameters.as_mut().filter(|parameters| parameters.0.contains_key(name)) {
+                            Some(parameters) => {
+                                bevy_inspector_egui::reflect_inspector::ui_for_value(parameters.0.get_mut(name).unwrap().as_mut(), ui, &type_registry);
+                                if ui.button("reset").clicked() {
+                                    parameters.0.remove(name);
+                                }
+                            }
+                            None => {
+                                bevy_inspector_egui::reflect_inspector::ui_for_value_readonly(default, ui, &type_registry);
+                                if ui.button("override").clicked() {
+                                    match parameters.as_mut() {
+                                        Some(parameters) => {
+                                            parameters.0.insert(name.to_string(), default.clone_value());
+                                        }
+                                        None => {
+                                            commands.entity(entity).insert(ScriptParameters(HashMap::from([(name.to_string(), default.clone_value())])));
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    });
+                }
+            });
+        }
+        for (e, t) in transforms.iter() {
+            ui.collapsing(format!("{}", e), |ui| {
+                bevy_inspector_egui::reflect_inspector::ui_for_value_readonly(t, ui, &*viewer.type_registry.as_ref().unwrap().read());
+            });
+        }
+    });
+}
+
+fn elapsed_seconds(_: In<()>, time: Res<Time>) -> f32 {
+    time.elapsed_seconds()
+}
+
+fn add_i32(a: i32, b: i32) -> i32 {
+    a + b
+}
+
+fn add_f32(a: f32, b: f32) -> f32 {
+    a + b
+}
+
+/// Linearly interpolates between two values.
+#[script_function(category = "Math", pure, returns = "`from` when `t` is 0, `to` when `t` is 1")]
+fn lerp(from: f32, to: f32, t: f32) -> f32 {
+    from + (to - from) * t
+}
+
+/// Restricts a value to the range between `min` and `max`.
+#[script_function(category = "Math", pure)]
+fn clamp(value: f32, min: f32, max: f32) -> f32 {
+    value.clamp(min, max)
+}
+
+fn functions() -> HashMap<String, (Function<'static>, u32)> {
+    let mut functions = HashMap::new();
+
+    functions.insert(
+        "add_i32".to_string(),
+        ((|a: i32, b: i32| a + b).into_function(), 2),
+    );
+    functions.insert(
+        "print_i32".to_string(),
+        ((|a: i32| println!("{a}")).into_function(), 1),
+    );
+    functions
+}
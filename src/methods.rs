@@ -0,0 +1,112 @@
+use bevy::prelude::*;
+use bevy::reflect::func::args::Ownership;
+use crate::registry::{insert_function, FunctionDescriptor, FunctionKind, ReflectedFunction, RegisteredFunction};
+
+/// A type whose curated methods can be registered in one call with
+/// [`RegisterMethods::register_methods`], see `curated_methods!` for the ones this crate provides.
+///
+/// Methods are registered as `Type::method` under a "Type" submenu, receivers are the
+/// first parameter so `&self` and `&mut self` borrow whatever is wired into that pin.
+pub trait ScriptMethods: TypePath {
+    fn script_methods() -> Vec<ScriptMethod>;
+}
+
+pub struct ScriptMethod {
+    pub name: &'static str,
+    /// In parameter order, the receiver is `self`.
+    pub params: &'static [&'static str],
+    pub function: ReflectedFunction,
+}
+
+pub trait RegisterMethods {
+    fn register_methods<T: ScriptMethods>(&mut self) -> &mut Self;
+}
+
+impl RegisterMethods for App {
+    fn register_methods<T: ScriptMethods>(&mut self) -> &mut Self {
+        let type_name = T::short_type_path();
+        for ScriptMethod { name: method, params, function } in T::script_methods() {
+            // without a `&mut` parameter the method can only produce values
+            let pure = function.info().return_info().type_path() != "()"
+                && function
+                    .info()
+                    .args()
+                    .iter()
+                    .all(|arg| !matches!(arg.ownership(), Ownership::Mut));
+            let mut descriptor = FunctionDescriptor::new().with_category(type_name);
+            for param in params {
+                descriptor = descriptor.with_param(*param, "");
+            }
+            if pure {
+                descriptor = descriptor.pure();
+            }
+            insert_function(self, format!("{}::{}", type_name, method), RegisteredFunction {
+                kind: FunctionKind::Reflected(function.with_rust_path(format!("{}::{}", type_name, method))),
+                descriptor,
+            });
+        }
+        self
+    }
+}
+
+/// Implements [`ScriptMethods`] for a type from a curated list of its methods.
+///
+/// Reflection doesn't list a type's methods, so they are picked by hand. Parameters are named
+/// like in the method's signature, `self` included, and become the pin names.
+macro_rules! curated_methods {
+    ($ty:ty: $($method:ident($($param:ident),*)),* $(,)?) => {
+        impl ScriptMethods for $ty {
+            fn script_methods() -> Vec<ScriptMethod> {
+                vec![$(ScriptMethod {
+                    name: stringify!($method),
+                    params: &[$(stringify!($param)),*],
+                    function: ReflectedFunction::new(<$ty>::$method),
+                }),*]
+            }
+        }
+    };
+}
+
+curated_methods!(Transform:
+    rotate(self, rotation),
+    rotate_x(self, angle),
+    rotate_y(self, angle),
+    rotate_z(self, angle),
+    rotate_local(self, rotation),
+    rotate_local_x(self, angle),
+    rotate_local_y(self, angle),
+    rotate_local_z(self, angle),
+    translate_around(self, point, rotation),
+    rotate_around(self, point, rotation),
+    mul_transform(self, transform),
+    transform_point(self, point),
+    with_translation(self, translation),
+    with_rotation(self, rotation),
+    with_scale(self, scale),
+);
+
+curated_methods!(Vec3:
+    normalize(self),
+    normalize_or_zero(self),
+    length(self),
+    length_squared(self),
+    dot(self, rhs),
+    cross(self, rhs),
+    distance(self, rhs),
+    lerp(self, rhs, s),
+    min(self, rhs),
+    max(self, rhs),
+    abs(self),
+);
+
+curated_methods!(Quat:
+    from_rotation_x(angle),
+    from_rotation_y(angle),
+    from_rotation_z(angle),
+    from_axis_angle(axis, angle),
+    mul_quat(self, rhs),
+    mul_vec3(self, rhs),
+    inverse(self),
+    normalize(self),
+    slerp(self, end, s),
+);
@@ -0,0 +1,350 @@
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+use bevy::ecs::component::ComponentId;
+use bevy::reflect::serde::ReflectSerializer;
+use bevy::reflect::{Reflect, TypeInfo, TypeRegistry};
+use serde::{Deserialize, Serialize};
+use crate::graph_file::{deserialize_value, query_components, GraphFileError};
+use crate::indirect_stack::{StackHandle, StackValue};
+use crate::registry::{ComponentMap, FunctionRegistry};
+use crate::virtual_machine::Bytecode;
+
+pub const PROGRAM_FORMAT_VERSION: u32 = 1;
+
+/// A compiled graph as stored on disk, so it can run without the editor or compiler.
+///
+/// Constants live in a pool and are stored through `ReflectSerializer`, components by their type
+/// path. Both are resolved against the registries again when the program is loaded, and calls are
+/// checked against the functions registered then.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Program {
+    pub version: u32,
+    pub constants: Vec<serde_json::Value>,
+    pub instructions: Vec<Instruction>,
+}
+
+/// [`Bytecode`] with indices into the constant pool instead of values.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum Instruction {
+    Push(usize),
+    Pop,
+    /// The function's arity and version when the program was saved.
+    Call {
+        id: String,
+        args: usize,
+        version: u32,
+        returns: bool,
+    },
+    GetField(usize, String),
+    SetField(usize),
+    Query {
+        components: Vec<String>,
+    },
+    SelfEntity {
+        components: Vec<String>,
+    },
+    Copy(usize),
+    Parameter {
+        name: String,
+        default: usize,
+    },
+    Ref(usize),
+    Mut(usize),
+}
+
+#[derive(Debug)]
+pub enum ProgramError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    UnsupportedVersion(u32),
+    /// The compiler only pushes owned values, anything else only exists while the program runs.
+    Unserializable(String),
+    Constant(usize),
+    /// A type, component or value that couldn't be resolved while loading.
+    Resolve(GraphFileError),
+    UnknownFunction(String),
+    /// The function was registered with a different signature or version than when the program was saved.
+    CallMismatch(String),
+    /// Instructions the compiler doesn't produce, e.g. a handle past the top of the stack.
+    Invalid {
+        instruction: usize,
+        message: String,
+    },
+}
+
+impl Display for ProgramError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProgramError::Io(err) => write!(f, "{}", err),
+            ProgramError::Json(err) => write!(f, "{}", err),
+            ProgramError::UnsupportedVersion(version) => write!(f, "program format version {} is not supported", version),
+            ProgramError::Unserializable(what) => write!(f, "{} can't be stored in a program", what),
+            ProgramError::Constant(index) => write!(f, "the program has no constant {}", index),
+            ProgramError::Resolve(err) => write!(f, "{}", err),
+            ProgramError::UnknownFunction(id) => write!(f, "no function with id `{}` is registered", id),
+            ProgramError::CallMismatch(id) => write!(f, "`{}` changed since the program was saved", id),
+            ProgramError::Invalid { instruction, message } => write!(f, "instruction {}: {}", instruction, message),
+        }
+    }
+}
+
+impl std::error::Error for ProgramError {}
+
+impl From<std::io::Error> for ProgramError {
+    fn from(err: std::io::Error) -> Self {
+        ProgramError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for ProgramError {
+    fn from(err: serde_json::Error) -> Self {
+        ProgramError::Json(err)
+    }
+}
+
+impl From<GraphFileError> for ProgramError {
+    fn from(err: GraphFileError) -> Self {
+        ProgramError::Resolve(err)
+    }
+}
+
+impl Program {
+    pub fn from_bytecode(bytecode: &[Bytecode], function_registry: &FunctionRegistry, type_registry: &TypeRegistry) -> Result<Self, ProgramError> {
+        let mut constants = vec![];
+        let mut constant = |value: &dyn Reflect| -> Result<usize, ProgramError> {
+            let value = serde_json::to_value(ReflectSerializer::new(value, type_registry))?;
+            // equal constants share a slot
+            Ok(match constants.iter().position(|constant| *constant == value) {
+                Some(index) => index,
+                None => {
+                    constants.push(value);
+                    constants.len() - 1
+                }
+            })
+        };
+        let components = |components: &[(String, ComponentId, TypeInfo)]| -> Vec<String> {
+            components
+                .iter()
+                .map(|(_, _, type_info)| type_info.type_path().to_string())
+                .collect()
+        };
+        let mut instructions = vec![];
+        for instruction in bytecode {
+            instructions.push(match instruction {
+                Bytecode::Push(StackValue::Owned(value)) => Instruction::Push(constant(value.as_ref())?),
+                Bytecode::Push(value) => return Err(ProgramError::Unserializable(format!("{:?}", value))),
+                Bytecode::Pop => Instruction::Pop,
+                Bytecode::Call(id) => {
+                    let registered = function_registry
+                        .resolve(id)
+                        .and_then(|name| function_registry.functions.get(name))
+                        .ok_or_else(|| ProgramError::UnknownFunction(id.clone()))?;
+                    Instruction::Call {
+                        id: id.clone(),
+                        args: registered.signature().arg_count(),
+                        version: registered.descriptor.version,
+                        returns: registered.signature().return_info().type_path() != "()",
+                    }
+                }
+                Bytecode::GetField(handle, name) => Instruction::GetField(handle.0, name.clone()),
+                Bytecode::SetField(handle) => Instruction::SetField(handle.0),
+                Bytecode::Query { components: query } => Instruction::Query {
+                    components: components(query),
+                },
+                Bytecode::SelfEntity { components: query } => Instruction::SelfEntity {
+                    components: components(query),
+                },
+                Bytecode::Copy(handle) => Instruction::Copy(handle.0),
+                Bytecode::Parameter { name, default } => Instruction::Parameter {
+                    name: name.clone(),
+                    default: constant(default.as_ref())?,
+                },
+                Bytecode::Ref(handle) => Instruction::Ref(handle.0),
+                Bytecode::Mut(handle) => Instruction::Mut(handle.0),
+            });
+        }
+        Ok(Program {
+            version: PROGRAM_FORMAT_VERSION,
+            constants,
+            instructions,
+        })
+    }
+
+    /// Checks that the program runs on the VM without reaching past its stack: it starts with its
+    /// only query, every instruction finds the values it pops and handles point below the top.
+    ///
+    /// Calls are checked against the registered functions, the stack depth follows their
+    /// signatures rather than what the file claims.
+    pub fn validate(&self, function_registry: &FunctionRegistry) -> Result<(), ProgramError> {
+        let invalid = |instruction: usize, message: &str| ProgramError::Invalid {
+            instruction,
+            message: message.to_string(),
+        };
+        let mut depth = match self.instructions.first() {
+            Some(Instruction::Query { components } | Instruction::SelfEntity { components }) => components.len(),
+            _ => return Err(invalid(0, "a program starts with a query or self")),
+        };
+        for (index, instruction) in self.instructions.iter().enumerate().skip(1) {
+            let pop = |depth: usize, count: usize| {
+                depth
+                    .checked_sub(count)
+                    .ok_or_else(|| invalid(index, "pops more values than are on the stack"))
+            };
+            let handle = |handle: usize, depth: usize| {
+                (handle < depth)
+                    .then_some(())
+                    .ok_or_else(|| invalid(index, "refers to a slot past the top of the stack"))
+            };
+            depth = match instruction {
+                Instruction::Push(_) | Instruction::Parameter { .. } => depth + 1,
+                Instruction::Copy(slot)
+                | Instruction::GetField(slot, _)
+                | Instruction::Ref(slot)
+                | Instruction::Mut(slot) => {
+                    handle(*slot, depth)?;
+                    depth + 1
+                }
+                Instruction::Pop => pop(depth, 1)?,
+                Instruction::Call { id, args, version, returns } => {
+                    let registered = function_registry
+                        .resolve(id)
+                        .and_then(|name| function_registry.functions.get(name))
+                        .ok_or_else(|| ProgramError::UnknownFunction(id.clone()))?;
+                    let signature = registered.signature();
+                    let registered_returns = signature.return_info().type_path() != "()";
+                    if signature.arg_count() != *args || registered_returns != *returns || registered.descriptor.version != *version {
+                        return Err(ProgramError::CallMismatch(id.clone()));
+                    }
+                    pop(depth, signature.arg_count())? + usize::from(registered_returns)
+                }
+                Instruction::SetField(slot) => {
+                    // the value is popped before the target is looked up
+                    let depth = pop(depth, 1)?;
+                    handle(*slot, depth)?;
+                    depth
+                }
+                Instruction::Query { .. } | Instruction::SelfEntity { .. } => {
+                    return Err(invalid(index, "only the first instruction can be a query or self"));
+                }
+            };
+        }
+        Ok(())
+    }
+
+    /// Resolves the constants and component paths after validating the program.
+    pub fn to_bytecode(&self, function_registry: &FunctionRegistry, type_registry: &TypeRegistry, component_map: &ComponentMap) -> Result<Vec<Bytecode>, ProgramError> {
+        if self.version != PROGRAM_FORMAT_VERSION {
+            return Err(ProgramError::UnsupportedVersion(self.version));
+        }
+        self.validate(function_registry)?;
+        let constant = |index: usize| -> Result<_, ProgramError> {
+            let value = self.constants.get(index).ok_or(ProgramError::Constant(index))?;
+            Ok(deserialize_value(value, type_registry)?)
+        };
+        let components = |components: &[String]| -> Result<_, ProgramError> {
+            Ok(query_components(components, type_registry, component_map)?.components)
+        };
+        self.instructions
+            .iter()
+            .map(|instruction| -> Result<Bytecode, ProgramError> {
+                Ok(match instruction {
+                    Instruction::Push(index) => Bytecode::Push(StackValue::Owned(constant(*index)?)),
+                    Instruction::Pop => Bytecode::Pop,
+                    Instruction::Call { id, .. } => Bytecode::Call(id.clone()),
+                    Instruction::GetField(handle, name) => Bytecode::GetField(StackHandle(*handle), name.clone()),
+                    Instruction::SetField(handle) => Bytecode::SetField(StackHandle(*handle)),
+                    Instruction::Query { components: query } => Bytecode::Query {
+                        components: components(query)?,
+                    },
+                    Instruction::SelfEntity { components: query } => Bytecode::SelfEntity {
+                        components: components(query)?,
+                    },
+                    Instruction::Copy(handle) => Bytecode::Copy(StackHandle(*handle)),
+                    Instruction::Parameter { name, default } => Bytecode::Parameter {
+                        name: name.clone(),
+                        default: constant(*default)?,
+                    },
+                    Instruction::Ref(handle) => Bytecode::Ref(StackHandle(*handle)),
+                    Instruction::Mut(handle) => Bytecode::Mut(StackHandle(*handle)),
+                })
+            })
+            .collect()
+    }
+}
+
+pub fn save_program(path: impl AsRef<Path>, bytecode: &[Bytecode], function_registry: &FunctionRegistry, type_registry: &TypeRegistry) -> Result<(), ProgramError> {
+    let program = Program::from_bytecode(bytecode, function_registry, type_registry)?;
+    if let Some(parent) = path.as_ref().parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, serde_json::to_string_pretty(&program)?)?;
+    Ok(())
+}
+
+pub fn load_program(
+    path: impl AsRef<Path>,
+    function_registry: &FunctionRegistry,
+    type_registry: &TypeRegistry,
+    component_map: &ComponentMap,
+) -> Result<Vec<Bytecode>, ProgramError> {
+    let program: Program = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+    program.to_bytecode(function_registry, type_registry, component_map)
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::AppTypeRegistry;
+    use bevy::reflect::Typed;
+    use crate::registry::FunctionDescriptor;
+    use crate::test_support::{position_world, sub, Position};
+    use crate::virtual_machine::run;
+    use super::*;
+
+    #[test]
+    fn saved_program_loads_and_runs() {
+        let (mut world, component_map) = position_world();
+        let entity = world.spawn(Position { x: 0.0, y: 0.0 }).id();
+        let component_id = world.component_id::<Position>().unwrap();
+        let mut function_registry = FunctionRegistry::default();
+        function_registry.register("sub", sub, FunctionDescriptor::new());
+
+        let bytecode = vec![
+            Bytecode::Query {
+                components: vec![("Position".to_string(), component_id, Position::type_info().clone())],
+            },
+            Bytecode::Push(StackValue::Owned(Box::new(1.0_f32))),
+            Bytecode::Push(StackValue::Owned(Box::new(3.0_f32))),
+            Bytecode::Copy(StackHandle(1)),
+            Bytecode::Copy(StackHandle(2)),
+            Bytecode::Call("sub".to_string()),
+            Bytecode::GetField(StackHandle(0), "x".to_string()),
+            Bytecode::Ref(StackHandle(3)),
+            Bytecode::SetField(StackHandle(4)),
+        ];
+        let path = std::env::temp_dir().join(format!("lek_program_test_{}.lekprogram", std::process::id()));
+        let loaded = {
+            let type_registry = world.resource::<AppTypeRegistry>().read();
+            save_program(&path, &bytecode, &function_registry, &type_registry).unwrap();
+            load_program(&path, &function_registry, &type_registry, &component_map)
+        };
+        std::fs::remove_file(&path).unwrap();
+        run(&loaded.unwrap(), &function_registry, None, &mut world).unwrap();
+        assert_eq!(world.get::<Position>(entity).unwrap().x, -2.0);
+    }
+
+    #[test]
+    fn handles_past_the_top_are_rejected() {
+        let program = Program {
+            version: PROGRAM_FORMAT_VERSION,
+            constants: vec![],
+            instructions: vec![
+                Instruction::SelfEntity { components: vec![] },
+                Instruction::Ref(0),
+            ],
+        };
+        assert!(matches!(
+            program.validate(&FunctionRegistry::default()),
+            Err(ProgramError::Invalid { instruction: 1, .. })
+        ));
+    }
+}
@@ -2,51 +2,720 @@ use bevy::app::{App, Plugin};
 use bevy::ecs::component::ComponentId;
 use bevy::ecs::system::RunSystemOnce;
 use bevy::prelude::*;
-use bevy::reflect::func::{Function, IntoFunction};
+use bevy::reflect::func::args::{ArgInfo, GetOwnership};
+use bevy::reflect::func::{Function, FunctionInfo, IntoFunction, ReturnInfo};
+use bevy::reflect::TypePath;
 use std::any::TypeId;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use crate::scripting::FunctionReturn;
 
+/// A function declared with `#[script_function]`, collected with `module_fns!`.
+pub struct ScriptFunction {
+    pub name: &'static str,
+    pub function: ReflectedFunction,
+    pub descriptor: FunctionDescriptor,
+}
+
+pub trait RegisterScriptFunctions {
+    fn register_script_functions(&mut self, functions: Vec<ScriptFunction>) -> &mut Self;
+}
+
+impl RegisterScriptFunctions for App {
+    fn register_script_functions(&mut self, functions: Vec<ScriptFunction>) -> &mut Self {
+        for script_function in functions {
+            insert_function(self, script_function.name.to_string(), RegisteredFunction {
+                kind: FunctionKind::Reflected(script_function.function),
+                descriptor: script_function.descriptor,
+            });
+        }
+        self
+    }
+}
+
+/// Registration of functions that are `Send + Sync`, see [`RegisterMainThreadFunction`] for the others.
 pub trait RegisterFunction<T> {
-    fn register_function(&mut self, name: impl AsRef<str>, function: impl IntoFunction<'static, T>);
+    fn register_function(
+        &mut self,
+        name: impl AsRef<str>,
+        function: impl IntoFunction<'static, T> + Clone + Send + Sync + 'static,
+    );
+    /// Registers a function without side effects, its nodes have no flow pins and are
+    /// evaluated whenever their output is needed.
+    fn register_pure_function(
+        &mut self,
+        name: impl AsRef<str>,
+        function: impl IntoFunction<'static, T> + Clone + Send + Sync + 'static,
+    );
+    fn register_function_with_descriptor(
+        &mut self,
+        name: impl AsRef<str>,
+        function: impl IntoFunction<'static, T> + Clone + Send + Sync + 'static,
+        descriptor: FunctionDescriptor,
+    );
+    /// Registers `function` as `name` and adds it to the overload set `overload`.
+    ///
+    /// Graphs only show one node for the whole set, the compiler picks the overload whose
+    /// parameter types match the wired inputs. All overloads of a set need the same number of
+    /// parameters and return values, and have to agree on purity, registering one that doesn't
+    /// panics.
+    fn register_overload(
+        &mut self,
+        overload: impl AsRef<str>,
+        name: impl AsRef<str>,
+        function: impl IntoFunction<'static, T> + Clone + Send + Sync + 'static,
+        descriptor: FunctionDescriptor,
+    );
 }
 
 impl<T> RegisterFunction<T> for App {
     fn register_function(
         &mut self,
         name: impl AsRef<str>,
-        function: impl IntoFunction<'static, T>,
+        function: impl IntoFunction<'static, T> + Clone + Send + Sync + 'static,
+    ) {
+        self.register_function_with_descriptor(name, function, FunctionDescriptor::new());
+    }
+
+    fn register_pure_function(
+        &mut self,
+        name: impl AsRef<str>,
+        function: impl IntoFunction<'static, T> + Clone + Send + Sync + 'static,
+    ) {
+        self.register_function_with_descriptor(name, function, FunctionDescriptor::new().pure());
+    }
+
+    fn register_function_with_descriptor(
+        &mut self,
+        name: impl AsRef<str>,
+        function: impl IntoFunction<'static, T> + Clone + Send + Sync + 'static,
+        descriptor: FunctionDescriptor,
+    ) {
+        insert_function(self, name.as_ref().to_string(), RegisteredFunction {
+            kind: FunctionKind::Reflected(ReflectedFunction::new(function)),
+            descriptor,
+        });
+    }
+
+    fn register_overload(
+        &mut self,
+        overload: impl AsRef<str>,
+        name: impl AsRef<str>,
+        function: impl IntoFunction<'static, T> + Clone + Send + Sync + 'static,
+        descriptor: FunctionDescriptor,
     ) {
-        let input = (name.as_ref().to_string(), function.into_function());
+        let name = name.as_ref().to_string();
+        self.register_function_with_descriptor(&name, function, descriptor);
         self.world_mut().run_system_once_with(
-            (input),
-            |thing: In<(String, Function<'static>)>, mut res: NonSendMut<FunctionRegistry>| -> () {
-                res.0.insert(thing.0 .0, thing.0 .1);
+            (overload.as_ref().to_string(), name),
+            |thing: In<(String, String)>, mut res: ResMut<FunctionRegistry>, type_registry: Res<AppTypeRegistry>| -> () {
+                let (overload, name) = thing.0;
+                if let Some(first) = res.overloads.get(&overload).and_then(|a| a.first()) {
+                    let first = res.functions.get(first).unwrap();
+                    let new = res.functions.get(&name).unwrap();
+                    let type_registry = type_registry.read();
+                    // the node's output pins come from the first overload
+                    let same_returns = FunctionReturn::from_info(first.signature(), &type_registry)
+                        .same_shape(&FunctionReturn::from_info(new.signature(), &type_registry));
+                    if first.signature().arg_count() != new.signature().arg_count()
+                        || first.descriptor.pure != new.descriptor.pure
+                        || !same_returns
+                    {
+                        panic!("`{}` doesn't match the other overloads of `{}`", name, overload);
+                    }
+                }
+                res.changed.insert(overload.clone());
+                res.overloads.entry(overload).or_default().push(name);
             },
         );
     }
 }
 
+/// # Panics
+///
+/// If the function returns a tuple that isn't in the type registry, graphs need its type info to
+/// give every element its own pin.
+pub(crate) fn insert_function(app: &mut App, name: String, function: RegisteredFunction) {
+    let return_type = function.signature().return_info().type_path();
+    if return_type.starts_with('(')
+        && return_type != "()"
+        && app.world().resource::<AppTypeRegistry>().read().get_with_type_path(return_type).is_none()
+    {
+        panic!("`{}` returns `{}`, register it with `App::register_type` before the function", name, return_type);
+    }
+    app.world_mut().run_system_once_with(
+        (name, function),
+        |thing: In<(String, RegisteredFunction)>, mut res: ResMut<FunctionRegistry>| -> () {
+            res.insert(thing.0 .0, thing.0 .1);
+        },
+    );
+}
+
+/// The parameters of a world function, the tuple it takes through `In`, e.g. `In<(f32, Vec3)>`.
+///
+/// World functions get copies, arguments a graph holds by reference are cloned off the stack.
+pub trait ScriptArgs: Sized + Send + 'static {
+    fn arg_infos() -> Vec<ArgInfo>;
+    /// `None` if the values don't fit the tuple.
+    fn from_values(values: Vec<Box<dyn Reflect>>) -> Option<Self>;
+}
+
+macro_rules! impl_script_args {
+    ($($arg:ident),*) => {
+        impl<$($arg: FromReflect + TypePath + GetOwnership + Send),*> ScriptArgs for ($($arg,)*) {
+            #[allow(unused_mut)]
+            fn arg_infos() -> Vec<ArgInfo> {
+                let mut infos = vec![];
+                $(infos.push(ArgInfo::new::<$arg>(infos.len()));)*
+                infos
+            }
+
+            #[allow(unused_mut)]
+            fn from_values(values: Vec<Box<dyn Reflect>>) -> Option<Self> {
+                let mut values = values.into_iter();
+                let args = ($($arg::from_reflect(values.next()?.as_ref())?,)*);
+                values.next().is_none().then_some(args)
+            }
+        }
+    };
+}
+
+impl_script_args!();
+impl_script_args!(A);
+impl_script_args!(A, B);
+impl_script_args!(A, B, C);
+impl_script_args!(A, B, C, D);
+impl_script_args!(A, B, C, D, E);
+impl_script_args!(A, B, C, D, E, F);
+impl_script_args!(A, B, C, D, E, F, G);
+impl_script_args!(A, B, C, D, E, F, G, H);
+
+/// What a world function leaves on the stack, nothing for functions returning `()`.
+pub type ScriptReturn = Option<Box<dyn Reflect>>;
+
+/// A function that gets access to the world when the script calls it, registered with
+/// [`RegisterWorldFunction::register_world_function`].
+///
+/// The system sits behind a lock so scripts only need a shared [`FunctionRegistry`] to call it.
+pub struct WorldFunction {
+    info: FunctionInfo,
+    system: Mutex<Box<dyn FnMut(Vec<Box<dyn Reflect>>, &mut World) -> Result<ScriptReturn, String> + Send>>,
+}
+
+impl WorldFunction {
+    /// The signature comes from the system's input and output, the system is initialized on `world`.
+    ///
+    /// # Panics
+    ///
+    /// If the system reads [`FunctionRegistry`] or [`AppTypeRegistry`], both are taken out of the
+    /// world while a script runs.
+    fn new<A: ScriptArgs, R: Reflect + TypePath + GetOwnership, M>(
+        name: String,
+        system: impl IntoSystem<A, R, M>,
+        world: &mut World,
+    ) -> Self {
+        let mut system = IntoSystem::into_system(system);
+        system.initialize(world);
+        let access = system.component_access();
+        let taken = [
+            ("FunctionRegistry", world.components().resource_id::<FunctionRegistry>()),
+            ("AppTypeRegistry", world.components().resource_id::<AppTypeRegistry>()),
+        ];
+        for (resource, id) in taken {
+            if !access.has_read_all() && id.is_some_and(|id| access.has_read(id)) {
+                panic!("world function `{}` can't take `{}`, it isn't in the world while scripts run", name, resource);
+            }
+        }
+        let info = FunctionInfo::new()
+            .with_name(name)
+            .with_args(A::arg_infos())
+            .with_return_info(ReturnInfo::new::<R>());
+        let returns_unit = TypeId::of::<R>() == TypeId::of::<()>();
+        WorldFunction {
+            info,
+            system: Mutex::new(Box::new(move |values, world| {
+                let args = A::from_values(values).ok_or_else(|| "the arguments don't fit the signature".to_string())?;
+                let returned = system.run(args, world);
+                system.apply_deferred(world);
+                Ok((!returns_unit).then(|| Box::new(returned) as Box<dyn Reflect>))
+            })),
+        }
+    }
+
+    pub fn call(&self, args: Vec<Box<dyn Reflect>>, world: &mut World) -> Result<ScriptReturn, String> {
+        (self.system.lock().unwrap())(args, world)
+    }
+}
+
+pub trait RegisterWorldFunction {
+    /// Registers a system as a script function.
+    ///
+    /// The system takes its arguments as a tuple through `In` and can take `&mut World` or any
+    /// other system params, the VM runs it on the world the script runs on. The signature scripts
+    /// see is the input tuple and the output, parameter names come from the descriptor.
+    ///
+    /// [`FunctionRegistry`] and [`AppTypeRegistry`] are not in the world while a script runs,
+    /// taking them as params panics here. A system taking `&mut World` has to stay away from them
+    /// too, as well as from the resources the system running the script holds on to.
+    ///
+    /// ```ignore
+    /// fn elapsed_seconds(_: In<()>, time: Res<Time>) -> f32 {
+    ///     time.elapsed_seconds()
+    /// }
+    ///
+    /// app.register_world_function(
+    ///     "elapsed_seconds",
+    ///     elapsed_seconds,
+    ///     FunctionDescriptor::new().with_category("Time"),
+    /// );
+    /// ```
+    fn register_world_function<A: ScriptArgs, R: Reflect + TypePath + GetOwnership, M>(
+        &mut self,
+        name: impl AsRef<str>,
+        system: impl IntoSystem<A, R, M>,
+        descriptor: FunctionDescriptor,
+    ) -> &mut Self;
+}
+
+impl RegisterWorldFunction for App {
+    fn register_world_function<A: ScriptArgs, R: Reflect + TypePath + GetOwnership, M>(
+        &mut self,
+        name: impl AsRef<str>,
+        system: impl IntoSystem<A, R, M>,
+        descriptor: FunctionDescriptor,
+    ) -> &mut Self {
+        let name = name.as_ref().to_string();
+        let world_function = WorldFunction::new(name.clone(), system, self.world_mut());
+        insert_function(self, name, RegisteredFunction {
+            kind: FunctionKind::World(world_function),
+            descriptor,
+        });
+        self
+    }
+}
+
 pub struct RegistryPlugin;
 
 impl Plugin for RegistryPlugin {
     fn build(&self, app: &mut App) {
-        app.insert_non_send_resource(FunctionRegistry::default());
-        app.add_systems(PostStartup, |world: &mut World| {
-            world.resource_scope(|world: &mut World, registry: Mut<AppTypeRegistry>| {
-                let mut map = HashMap::new();
-                for a in registry.read().iter() {
-                    if let Some(component_id) = world.components().get_id(a.type_id()) {
-                        map.insert(a.type_id(), component_id);
-                    }
-                }
-                world.insert_resource(ComponentMap(map));
-            });
+        app.init_resource::<FunctionRegistry>();
+        app.init_non_send_resource::<MainThreadFunctions>();
+        app.init_resource::<ComponentMap>();
+        app.add_systems(First, sync_component_map);
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct ComponentMap(pub HashMap<TypeId, ComponentEntry>);
+
+#[derive(Clone, Copy, Debug)]
+pub struct ComponentEntry {
+    pub id: ComponentId,
+    /// Whether the type is registered with `ReflectComponent`.
+    pub reflect_component: bool,
+}
+
+/// Adds reflected types to the [`ComponentMap`] once their components are initialised.
+///
+/// Only rescans when a type was registered or a component initialised since the last run,
+/// `seen` holds the type and component counts of that run. Type data can be added to a type
+/// that is already registered without changing the counts, so the `reflect_component` flags of
+/// the known components are checked every run.
+fn sync_component_map(world: &mut World, mut seen: Local<(usize, usize)>) {
+    let registry = world.resource::<AppTypeRegistry>().clone();
+    let registry = registry.read();
+    let changed_flags = world
+        .resource::<ComponentMap>()
+        .0
+        .iter()
+        .filter_map(|(type_id, entry)| {
+            let reflect_component = registry.get_type_data::<ReflectComponent>(*type_id).is_some();
+            (reflect_component != entry.reflect_component).then_some((*type_id, reflect_component))
+        })
+        .collect::<Vec<_>>();
+    // `resource_mut` marks the map as changed, only take it when a flag differs
+    if !changed_flags.is_empty() {
+        let mut component_map = world.resource_mut::<ComponentMap>();
+        for (type_id, reflect_component) in changed_flags {
+            component_map.0.get_mut(&type_id).unwrap().reflect_component = reflect_component;
+        }
+    }
+    let counts = (registry.iter().count(), world.components().len());
+    if counts == *seen {
+        return;
+    }
+    *seen = counts;
+    let mut new_entries = vec![];
+    let component_map = world.resource::<ComponentMap>();
+    for registration in registry.iter() {
+        if component_map.0.contains_key(&registration.type_id()) {
+            continue;
+        }
+        if let Some(id) = world.components().get_id(registration.type_id()) {
+            new_entries.push((registration.type_id(), ComponentEntry {
+                id,
+                reflect_component: registration.data::<ReflectComponent>().is_some(),
+            }));
+        }
+    }
+    if !new_entries.is_empty() {
+        world.resource_mut::<ComponentMap>().0.extend(new_entries);
+    }
+}
+
+/// Metadata shown in the editor and used by the compiler.
+///
+/// ```ignore
+/// app.register_function_with_descriptor(
+///     "lerp",
+///     lerp,
+///     FunctionDescriptor::new()
+///         .with_category("Math/Float")
+///         .with_description("Linearly interpolates between two values.")
+///         .with_param("from", "returned when `t` is 0")
+///         .with_param("to", "returned when `t` is 1")
+///         .with_param("t", "how far to go from `from` to `to`")
+///         .pure(),
+/// );
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct FunctionDescriptor {
+    /// Menu path, e.g. `["Math", "Float"]`.
+    pub category: Vec<String>,
+    pub description: Option<String>,
+    /// Indexed like the function's parameters.
+    pub params: Vec<ParamDescriptor>,
+    pub return_doc: Option<String>,
+    pub pure: bool,
+    /// Set by the registry, `false` for functions registered with
+    /// [`RegisterMainThreadFunction::register_main_thread_function`].
+    pub thread_safe: bool,
+    /// Stays the same when the function is renamed, graphs refer to functions by it.
+    /// Defaults to the registered name.
+    pub id: Option<String>,
+    /// Bumped whenever the signature changes, nodes made from an older version need migrating.
+    pub version: u32,
+    /// Names the function was registered under before.
+    pub aliases: Vec<String>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ParamDescriptor {
+    pub name: String,
+    pub doc: Option<String>,
+}
+
+impl FunctionDescriptor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Categories are separated by `/`.
+    pub fn with_category(mut self, category: impl AsRef<str>) -> Self {
+        self.category = category
+            .as_ref()
+            .split('/')
+            .filter(|a| !a.is_empty())
+            .map(|a| a.to_string())
+            .collect();
+        self
+    }
+
+    pub fn with_description(mut self, description: impl Into<String>) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+
+    /// Adds the next parameter, an empty `doc` means the parameter is only named.
+    pub fn with_param(mut self, name: impl Into<String>, doc: impl Into<String>) -> Self {
+        let doc = doc.into();
+        self.params.push(ParamDescriptor {
+            name: name.into(),
+            doc: (!doc.is_empty()).then_some(doc),
         });
+        self
+    }
+
+    pub fn with_return_doc(mut self, return_doc: impl Into<String>) -> Self {
+        self.return_doc = Some(return_doc.into());
+        self
+    }
+
+    pub fn pure(mut self) -> Self {
+        self.pure = true;
+        self
+    }
+
+    pub fn with_id(mut self, id: impl Into<String>) -> Self {
+        self.id = Some(id.into());
+        self
+    }
+
+    pub fn with_version(mut self, version: u32) -> Self {
+        self.version = version;
+        self
+    }
+
+    pub fn with_alias(mut self, alias: impl Into<String>) -> Self {
+        self.aliases.push(alias.into());
+        self
+    }
+
+    pub fn tooltip(&self) -> String {
+        let mut lines = vec![];
+        if let Some(description) = &self.description {
+            lines.push(description.clone());
+        }
+        for param in &self.params {
+            match &param.doc {
+                Some(doc) => lines.push(format!("{}: {}", param.name, doc)),
+                None => lines.push(param.name.clone()),
+            }
+        }
+        if let Some(return_doc) = &self.return_doc {
+            lines.push(format!("returns: {}", return_doc));
+        }
+        if self.pure {
+            lines.push("pure".to_string());
+        }
+        lines.join("\n")
     }
 }
 
-#[derive(Resource)]
-pub struct ComponentMap(pub HashMap<TypeId, ComponentId>);
+pub struct RegisteredFunction {
+    pub kind: FunctionKind,
+    pub descriptor: FunctionDescriptor,
+}
+
+pub enum FunctionKind {
+    Reflected(ReflectedFunction),
+    World(WorldFunction),
+    /// Called through [`MainThreadFunctions`], only the signature is kept here.
+    MainThread(FunctionInfo),
+}
+
+/// A reflected function that can be called from any thread.
+///
+/// [`Function`] isn't `Send`, so the registry keeps what it was made from and builds a fresh
+/// one for every call.
+#[derive(Clone)]
+pub struct ReflectedFunction {
+    info: FunctionInfo,
+    make: Arc<dyn Fn() -> Function<'static> + Send + Sync>,
+    rust_path: String,
+}
+
+impl ReflectedFunction {
+    pub fn new<T, F: IntoFunction<'static, T> + Clone + Send + Sync + 'static>(function: F) -> Self {
+        Self {
+            info: function.clone().into_function().info().clone(),
+            make: Arc::new(move || function.clone().into_function()),
+            // the path of a fn item, closures can't be named
+            rust_path: std::any::type_name::<F>().to_string(),
+        }
+    }
+
+    pub fn with_rust_path(mut self, rust_path: impl Into<String>) -> Self {
+        self.rust_path = rust_path.into();
+        self
+    }
+
+    pub fn info(&self) -> &FunctionInfo {
+        &self.info
+    }
+
+    /// How generated code calls the function, see [`crate::codegen`].
+    pub fn rust_path(&self) -> &str {
+        &self.rust_path
+    }
+
+    pub fn function(&self) -> Function<'static> {
+        (self.make)()
+    }
+}
 
+/// The functions that aren't `Send`, a non-send resource so they are only ever called on the
+/// main thread. Scripts calling them have to take it out of the world while they run.
 #[derive(Default)]
-pub struct FunctionRegistry(pub HashMap<String, Function<'static>>);
+pub struct MainThreadFunctions(pub HashMap<String, Function<'static>>);
+
+pub trait RegisterMainThreadFunction<T> {
+    /// Registers a function that isn't `Send + Sync`, e.g. one capturing an `Rc`.
+    fn register_main_thread_function(
+        &mut self,
+        name: impl AsRef<str>,
+        function: impl IntoFunction<'static, T>,
+        descriptor: FunctionDescriptor,
+    ) -> &mut Self;
+}
+
+impl<T> RegisterMainThreadFunction<T> for App {
+    fn register_main_thread_function(
+        &mut self,
+        name: impl AsRef<str>,
+        function: impl IntoFunction<'static, T>,
+        descriptor: FunctionDescriptor,
+    ) -> &mut Self {
+        let name = name.as_ref().to_string();
+        let function = function.into_function();
+        let info = function.info().clone();
+        self.world_mut()
+            .non_send_resource_mut::<MainThreadFunctions>()
+            .0
+            .insert(name.clone(), function);
+        insert_function(self, name, RegisteredFunction {
+            kind: FunctionKind::MainThread(info),
+            descriptor,
+        });
+        self
+    }
+}
+
+impl RegisteredFunction {
+    /// The function's info as registered.
+    pub fn signature(&self) -> &FunctionInfo {
+        match &self.kind {
+            FunctionKind::Reflected(function) => function.info(),
+            FunctionKind::World(world_function) => &world_function.info,
+            FunctionKind::MainThread(info) => info,
+        }
+    }
+
+    /// The function's info with the parameter names from the descriptor applied.
+    pub fn info(&self) -> FunctionInfo {
+        let args = self
+            .signature()
+            .args()
+            .iter()
+            .map(|arg| match self.descriptor.params.get(arg.index()) {
+                Some(param) => arg.clone().with_name(param.name.clone()),
+                None => arg.clone(),
+            })
+            .collect();
+        self.signature().clone().with_args(args)
+    }
+}
+
+/// Every function scripts can call. Only `Send + Sync` functions are stored here, so it is a
+/// plain resource any system can read, the others live in [`MainThreadFunctions`]. Running a
+/// script still takes an exclusive system, see [`run`](crate::virtual_machine::run).
+#[derive(Resource, Default)]
+pub struct FunctionRegistry {
+    /// Only change through [`FunctionRegistry::insert`] and [`FunctionRegistry::unregister`],
+    /// they keep the id index up to date.
+    pub functions: HashMap<String, RegisteredFunction>,
+    /// Overload set name to the names of its functions, in registration order.
+    pub overloads: HashMap<String, Vec<String>>,
+    /// Functions and overload sets added, replaced or removed since the last [`FunctionRegistry::take_changed`].
+    changed: HashSet<String>,
+    /// Stable id to the name the function is registered under.
+    ids: HashMap<String, String>,
+    /// Old name to the current one.
+    aliases: HashMap<String, String>,
+}
+
+impl FunctionRegistry {
+    /// Adds a function at runtime, replacing any function of the same name.
+    pub fn register<T>(
+        &mut self,
+        name: impl Into<String>,
+        function: impl IntoFunction<'static, T> + Clone + Send + Sync + 'static,
+        descriptor: FunctionDescriptor,
+    ) -> Option<RegisteredFunction> {
+        self.insert(name, RegisteredFunction {
+            kind: FunctionKind::Reflected(ReflectedFunction::new(function)),
+            descriptor,
+        })
+    }
+
+    /// # Panics
+    ///
+    /// If another function already has the same id, graphs couldn't tell the two apart.
+    pub fn insert(&mut self, name: impl Into<String>, mut function: RegisteredFunction) -> Option<RegisteredFunction> {
+        let name = name.into();
+        let id = function.descriptor.id.clone().unwrap_or_else(|| name.clone());
+        if let Some(other) = self.ids.get(&id).filter(|other| **other != name) {
+            panic!("`{}` has the id `{}` of `{}`", name, id, other);
+        }
+        function.descriptor.thread_safe = !matches!(function.kind, FunctionKind::MainThread(_));
+        self.mark_changed(&name);
+        self.unindex(&name);
+        self.ids.insert(id, name.clone());
+        for alias in &function.descriptor.aliases {
+            self.aliases.insert(alias.clone(), name.clone());
+        }
+        self.functions.insert(name, function)
+    }
+
+    /// Removes a function and drops it from its overload sets.
+    pub fn unregister(&mut self, name: &str) -> Option<RegisteredFunction> {
+        self.mark_changed(name);
+        for members in self.overloads.values_mut() {
+            members.retain(|member| member != name);
+        }
+        self.overloads.retain(|_, members| !members.is_empty());
+        self.unindex(name);
+        self.functions.remove(name)
+    }
+
+    fn unindex(&mut self, name: &str) {
+        self.ids.retain(|_, indexed| indexed != name);
+        self.aliases.retain(|_, indexed| indexed != name);
+    }
+
+    fn mark_changed(&mut self, name: &str) {
+        self.changed.insert(name.to_string());
+        for (overload, members) in self.overloads.iter() {
+            if members.iter().any(|member| member == name) {
+                self.changed.insert(overload.clone());
+            }
+        }
+    }
+
+    pub fn has_changed(&self) -> bool {
+        !self.changed.is_empty()
+    }
+
+    pub fn take_changed(&mut self) -> HashSet<String> {
+        std::mem::take(&mut self.changed)
+    }
+
+    /// The name a function is currently registered under, looked up by stable id, name or alias.
+    pub fn resolve(&self, id: &str) -> Option<&str> {
+        self.ids
+            .get(id)
+            .or_else(|| self.aliases.get(id))
+            .map(|name| name.as_str())
+    }
+
+    pub fn is_overload_member(&self, name: &str) -> bool {
+        self.overloads.values().any(|members| members.iter().any(|a| a == name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::any::TypeId;
+    use crate::test_support::Position;
+    use super::*;
+
+    #[test]
+    fn type_data_added_later_reaches_the_component_map() {
+        let mut world = World::new();
+        world.init_resource::<AppTypeRegistry>();
+        world.init_resource::<ComponentMap>();
+        world.resource::<AppTypeRegistry>().write().register::<Position>();
+        world.init_component::<Position>();
+        let sync = world.register_system(sync_component_map);
+        let reflect_component = |world: &World| world.resource::<ComponentMap>().0[&TypeId::of::<Position>()].reflect_component;
+
+        world.run_system(sync).unwrap();
+        assert!(!reflect_component(&world));
+        world
+            .resource::<AppTypeRegistry>()
+            .write()
+            .register_type_data::<Position, ReflectComponent>();
+        world.run_system(sync).unwrap();
+        assert!(reflect_component(&world));
+    }
+}
@@ -0,0 +1,146 @@
+use std::path::Path;
+use bevy::prelude::ReflectDefault;
+use bevy::reflect::func::args::Ownership;
+use bevy::reflect::{TypeInfo, TypeRegistry, VariantInfo};
+use serde_json::{json, Value};
+use crate::registry::{ComponentMap, FunctionRegistry};
+
+/// Describes everything a graph can use as JSON, for tools that don't link against the app.
+///
+/// The document has a `functions` array with the signatures and descriptors, `overloads`,
+/// `components` with the component ids and the `creatable` types (those with `ReflectDefault`),
+/// types are given by their full type path and reflected types come with their field layout.
+pub fn export_schema(function_registry: &FunctionRegistry, component_map: &ComponentMap, type_registry: &TypeRegistry) -> Value {
+    let mut functions = function_registry
+        .functions
+        .iter()
+        .map(|(name, function)| {
+            let info = function.info();
+            let mut args = info.args().iter().collect::<Vec<_>>();
+            args.sort_by_key(|arg| arg.index());
+            let descriptor = &function.descriptor;
+            json!({
+                "name": name,
+                "id": descriptor.id.as_deref().unwrap_or(name),
+                "version": descriptor.version,
+                "aliases": descriptor.aliases,
+                "category": descriptor.category,
+                "description": descriptor.description,
+                "pure": descriptor.pure,
+                "thread_safe": descriptor.thread_safe,
+                "args": args
+                    .iter()
+                    .map(|arg| json!({
+                        "name": arg.name(),
+                        "type": arg.type_path(),
+                        "ownership": match arg.ownership() {
+                            Ownership::Ref => "ref",
+                            Ownership::Mut => "mut",
+                            Ownership::Owned => "owned",
+                        },
+                        "doc": descriptor.params.get(arg.index()).and_then(|param| param.doc.clone()),
+                    }))
+                    .collect::<Vec<_>>(),
+                "return": {
+                    "type": info.return_info().type_path(),
+                    "doc": descriptor.return_doc,
+                },
+            })
+        })
+        .collect::<Vec<_>>();
+    functions.sort_by(|a, b| a["name"].as_str().cmp(&b["name"].as_str()));
+
+    let mut components = component_map
+        .0
+        .iter()
+        .filter_map(|(type_id, entry)| {
+            let registration = type_registry.get(*type_id)?;
+            Some(json!({
+                "type": registration.type_info().type_path(),
+                "component_id": entry.id.index(),
+                "reflect_component": entry.reflect_component,
+                "layout": type_layout(registration.type_info()),
+            }))
+        })
+        .collect::<Vec<_>>();
+    components.sort_by(|a, b| a["type"].as_str().cmp(&b["type"].as_str()));
+
+    let mut creatable = type_registry
+        .iter()
+        .filter(|registration| registration.data::<ReflectDefault>().is_some())
+        .map(|registration| json!({
+            "type": registration.type_info().type_path(),
+            "layout": type_layout(registration.type_info()),
+        }))
+        .collect::<Vec<_>>();
+    creatable.sort_by(|a, b| a["type"].as_str().cmp(&b["type"].as_str()));
+
+    json!({
+        "functions": functions,
+        "overloads": function_registry.overloads,
+        "components": components,
+        "creatable": creatable,
+    })
+}
+
+pub fn write_schema(
+    path: impl AsRef<Path>,
+    function_registry: &FunctionRegistry,
+    component_map: &ComponentMap,
+    type_registry: &TypeRegistry,
+) -> std::io::Result<()> {
+    let schema = export_schema(function_registry, component_map, type_registry);
+    std::fs::write(path, serde_json::to_string_pretty(&schema)?)
+}
+
+fn type_layout(type_info: &TypeInfo) -> Value {
+    match type_info {
+        TypeInfo::Struct(struct_info) => json!({
+            "kind": "struct",
+            "fields": struct_info
+                .iter()
+                .map(|field| json!({ "name": field.name(), "type": field.type_path() }))
+                .collect::<Vec<_>>(),
+        }),
+        TypeInfo::TupleStruct(tuple_struct_info) => json!({
+            "kind": "tuple_struct",
+            "fields": tuple_struct_info.iter().map(|field| field.type_path()).collect::<Vec<_>>(),
+        }),
+        TypeInfo::Tuple(tuple_info) => json!({
+            "kind": "tuple",
+            "fields": tuple_info.iter().map(|field| field.type_path()).collect::<Vec<_>>(),
+        }),
+        TypeInfo::List(list_info) => json!({ "kind": "list", "item": list_info.item_type_path_table().path() }),
+        TypeInfo::Array(array_info) => json!({
+            "kind": "array",
+            "item": array_info.item_type_path_table().path(),
+            "capacity": array_info.capacity(),
+        }),
+        TypeInfo::Map(map_info) => json!({
+            "kind": "map",
+            "key": map_info.key_type_path_table().path(),
+            "value": map_info.value_type_path_table().path(),
+        }),
+        TypeInfo::Enum(enum_info) => json!({
+            "kind": "enum",
+            "variants": enum_info
+                .iter()
+                .map(|variant| match variant {
+                    VariantInfo::Struct(variant) => json!({
+                        "name": variant.name(),
+                        "fields": variant
+                            .iter()
+                            .map(|field| json!({ "name": field.name(), "type": field.type_path() }))
+                            .collect::<Vec<_>>(),
+                    }),
+                    VariantInfo::Tuple(variant) => json!({
+                        "name": variant.name(),
+                        "fields": variant.iter().map(|field| field.type_path()).collect::<Vec<_>>(),
+                    }),
+                    VariantInfo::Unit(variant) => json!({ "name": variant.name() }),
+                })
+                .collect::<Vec<_>>(),
+        }),
+        TypeInfo::Value(_) => json!({ "kind": "value" }),
+    }
+}
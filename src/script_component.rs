@@ -0,0 +1,128 @@
+use std::collections::HashMap;
+use bevy::prelude::*;
+use crate::compiler::compile;
+use crate::graph_asset::Graph;
+use crate::registry::{ComponentMap, FunctionRegistry, MainThreadFunctions};
+use crate::virtual_machine::{run_on_entity, uses_main_thread, Bytecode};
+
+/// Runs a graph every frame for the entity it's attached to, the graph's self node stands
+/// for that entity. Graphs starting from a query aren't run.
+#[derive(Component, Reflect)]
+#[reflect(Component)]
+pub struct Script(pub Handle<Graph>);
+
+/// Per-entity overrides of the exposed parameters of its graph, by parameter name.
+///
+/// Overrides of a different type than the parameter are ignored.
+#[derive(Component, Default)]
+pub struct ScriptParameters(pub HashMap<String, Box<dyn Reflect>>);
+
+/// The compiled programs of the graphs used by [`Script`]s.
+#[derive(Resource, Default)]
+pub struct CompiledScripts(pub HashMap<AssetId<Graph>, Vec<Bytecode>>);
+
+pub struct ScriptComponentPlugin;
+
+impl Plugin for ScriptComponentPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<Script>();
+        app.init_resource::<CompiledScripts>();
+        app.add_systems(Update, (compile_scripts, run_scripts).chain());
+    }
+}
+
+/// Recompiles graphs when they are (re)loaded, and all of them when the function registry changed.
+///
+/// Only graphs whose root is a self node compile.
+fn compile_scripts(
+    mut events: EventReader<AssetEvent<Graph>>,
+    graphs: Res<Assets<Graph>>,
+    function_registry: Res<FunctionRegistry>,
+    type_registry: Res<AppTypeRegistry>,
+    component_map: Res<ComponentMap>,
+    mut compiled: ResMut<CompiledScripts>,
+) {
+    let mut dirty = vec![];
+    for event in events.read() {
+        match event {
+            AssetEvent::Added { id } | AssetEvent::Modified { id } => dirty.push(*id),
+            AssetEvent::Removed { id } => {
+                compiled.0.remove(id);
+            }
+            _ => {}
+        }
+    }
+    if function_registry.is_changed() {
+        dirty = graphs.ids().collect();
+    }
+    for id in dirty {
+        let Some(graph) = graphs.get(id) else {
+            continue;
+        };
+        let program = graph
+            .0
+            .to_snarl(&function_registry, &type_registry.read(), &component_map)
+            .map_err(|err| err.to_string())
+            .and_then(|snarl| compile(&snarl, &function_registry).map_err(|err| err.to_string()))
+            .and_then(|program| match program.first() {
+                Some(Bytecode::SelfEntity { .. }) => Ok(program),
+                // run per entity carrying the script, a query would run over all its matches each time
+                _ => Err("the graph starts from a query, scripts need a self node".to_string()),
+            });
+        match program {
+            Ok(program) => {
+                compiled.0.insert(id, program);
+            }
+            Err(err) => {
+                compiled.0.remove(&id);
+                println!("couldn't compile script {:?}: {}", id, err);
+            }
+        }
+    }
+}
+
+/// Errors are logged when they first happen for an entity, not every frame they repeat.
+fn run_scripts(world: &mut World, mut reported: Local<HashMap<Entity, String>>) {
+    let scripts = world
+        .query::<(Entity, &Script)>()
+        .iter(world)
+        .map(|(entity, script)| (entity, script.0.id()))
+        .collect::<Vec<_>>();
+    reported.retain(|reported, _| scripts.iter().any(|(entity, _)| entity == reported));
+    if scripts.is_empty() {
+        return;
+    }
+    world.resource_scope(|world, compiled: Mut<CompiledScripts>| {
+        world.resource_scope(|world, function_registry: Mut<FunctionRegistry>| {
+            let needs_main_thread = scripts.iter().any(|(_, id)| {
+                compiled
+                    .0
+                    .get(id)
+                    .is_some_and(|program| uses_main_thread(program, &function_registry))
+            });
+            let mut main_thread = needs_main_thread
+                .then(|| world.remove_non_send_resource::<MainThreadFunctions>())
+                .flatten();
+            for (entity, id) in scripts {
+                let Some(program) = compiled.0.get(&id) else {
+                    continue;
+                };
+                match run_on_entity(program, entity, &function_registry, main_thread.as_mut(), world) {
+                    Ok(()) => {
+                        reported.remove(&entity);
+                    }
+                    Err(err) => {
+                        let err = err.to_string();
+                        if reported.get(&entity) != Some(&err) {
+                            println!("script error on {}: {}", entity, err);
+                            reported.insert(entity, err);
+                        }
+                    }
+                }
+            }
+            if let Some(main_thread) = main_thread {
+                world.insert_non_send_resource(main_thread);
+            }
+        });
+    });
+}
@@ -1,14 +1,18 @@
-use crate::registry::{ComponentMap, FunctionRegistry};
+use crate::clipboard::{copy_selection, NodeSelection};
+use crate::graph_file::SavedSignature;
+use crate::registry::{ComponentMap, FunctionDescriptor, FunctionRegistry, RegisteredFunction};
 use crate::{NUMBER_COLOR, UNTYPED_COLOR};
 use bevy::ecs::component::ComponentId;
 use bevy::prelude::{AppTypeRegistry, ReflectDefault, Res, ResMut};
+use bevy::reflect::func::args::ArgInfo;
 use bevy::reflect::func::FunctionInfo;
-use bevy::reflect::{Reflect, ReflectMut, ReflectRef, TypeInfo};
+use bevy::reflect::{Reflect, ReflectMut, ReflectRef, TypeInfo, TypeRegistry};
 use bevy_egui::egui::{emath, menu, ComboBox, Pos2, ScrollArea, Ui};
 use egui_snarl::ui::{PinInfo, SnarlViewer};
 use egui_snarl::{InPin, NodeId, OutPin, Snarl};
 use serde::{Deserialize, Serialize};
 use std::any::Any;
+use std::collections::BTreeMap;
 
 #[derive(Clone, Debug)]
 pub enum ScriptNode {
@@ -16,7 +20,13 @@ pub enum ScriptNode {
     Field(FieldNode),
     Function(FunctionNode),
     TypeCreation(TypeCreationNode),
+    Parameter(ParameterNode),
     Query(QueryNode),
+    /// The root of a graph attached to an entity with a [`Script`](crate::script_component::Script)
+    /// component, its outputs are components of that entity.
+    SelfEntity(QueryNode),
+    /// A function node whose function wasn't registered when the graph was loaded.
+    Unresolved(UnresolvedNode),
 }
 
 impl ScriptNode {
@@ -24,16 +34,37 @@ impl ScriptNode {
         match self {
             ScriptNode::Set(_) => true,
             ScriptNode::Field(_) => false,
-            ScriptNode::Function(_) => true,
+            ScriptNode::Function(function_node) => !function_node.descriptor.pure,
             ScriptNode::TypeCreation(_) => false,
-            ScriptNode::Query(_) => true,
+            ScriptNode::Parameter(_) => false,
+            ScriptNode::Query(_) | ScriptNode::SelfEntity(_) => true,
+            // whether it had a flow pin isn't known, it never compiles anyway
+            ScriptNode::Unresolved(_) => true,
         }
     }
     fn set() -> Self {
         Self::Set(SetNode::new())
     }
-    fn function(function_info: FunctionInfo) -> Self {
-        Self::Function(FunctionNode::new(function_info))
+    fn function(function_info: FunctionInfo, descriptor: FunctionDescriptor, type_registry: &TypeRegistry) -> Self {
+        let returns = FunctionReturn::from_info(&function_info, type_registry);
+        Self::Function(FunctionNode::new(function_info, returns, descriptor))
+    }
+    /// A node calling the function or overload set registered as `name`.
+    pub(crate) fn registered_function(name: &str, function_registry: &FunctionRegistry, type_registry: &TypeRegistry) -> Option<Self> {
+        match function_registry.overloads.get(name) {
+            None => {
+                let f = function_registry.functions.get(name)?;
+                Some(ScriptNode::function(f.info().with_name(name.to_string()), f.descriptor.clone(), type_registry))
+            }
+            Some(members) => {
+                // the first overload stands in for the pin layout
+                let f = function_registry.functions.get(&members[0])?;
+                let returns = FunctionReturn::from_info(f.signature(), type_registry);
+                let mut function_node = FunctionNode::new(f.info().with_name(name.to_string()), returns, f.descriptor.clone());
+                function_node.overload = Some(name.to_string());
+                Some(ScriptNode::Function(function_node))
+            }
+        }
     }
     fn field() -> Self {
         Self::Field(FieldNode::new())
@@ -41,9 +72,15 @@ impl ScriptNode {
     fn type_creation(value: Box<dyn Reflect>) -> Self {
         Self::TypeCreation(TypeCreationNode::new(value))
     }
+    fn parameter(value: Box<dyn Reflect>) -> Self {
+        Self::Parameter(ParameterNode::new("parameter".to_string(), value))
+    }
     fn query() -> Self {
         Self::Query(QueryNode::new())
     }
+    fn self_entity() -> Self {
+        Self::SelfEntity(QueryNode::new())
+    }
 }
 #[derive(Clone, Debug)]
 pub struct SetNode {}
@@ -116,13 +153,172 @@ impl FieldNode {
 #[derive(Clone, Debug)]
 pub struct FunctionNode {
     pub function_info: FunctionInfo,
+    pub returns: FunctionReturn,
+    pub descriptor: FunctionDescriptor,
+    /// Set when the node stands for a whole overload set, the compiler resolves it to one
+    /// of the set's functions and `function_info` is only used for the pin layout.
+    pub overload: Option<String>,
+    /// The signature the node was saved with, `function_info` always comes from the registry.
+    pub saved_signature: Option<SavedSignature>,
+}
+
+/// The data outputs of a function node, tuples get one pin per element.
+#[derive(Clone, Debug)]
+pub enum FunctionReturn {
+    Unit,
+    Single(String),
+    Tuple(Vec<String>),
+}
+
+impl FunctionReturn {
+    /// Tuples are split if they are registered in the type registry, registering a function
+    /// returning an unregistered one on the `App` panics.
+    pub fn from_info(function_info: &FunctionInfo, type_registry: &TypeRegistry) -> Self {
+        let type_path = function_info.return_info().type_path();
+        if type_path == "()" {
+            return FunctionReturn::Unit;
+        }
+        match type_registry
+            .get_with_type_path(type_path)
+            .map(|registration| registration.type_info())
+        {
+            Some(TypeInfo::Tuple(tuple_info)) => FunctionReturn::Tuple(
+                tuple_info
+                    .iter()
+                    .map(|field| field.type_path().to_string())
+                    .collect(),
+            ),
+            _ => FunctionReturn::Single(type_path.to_string()),
+        }
+    }
+
+    pub fn types(&self) -> Vec<&str> {
+        match self {
+            FunctionReturn::Unit => vec![],
+            FunctionReturn::Single(type_path) => vec![type_path.as_str()],
+            FunctionReturn::Tuple(elements) => elements.iter().map(|a| a.as_str()).collect(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.types().len()
+    }
+
+    /// Whether both give a node the same output pins, whatever their types.
+    pub fn same_shape(&self, other: &FunctionReturn) -> bool {
+        std::mem::discriminant(self) == std::mem::discriminant(other) && self.len() == other.len()
+    }
 }
 
 impl FunctionNode {
-    pub fn new(function_info: FunctionInfo) -> Self {
-        FunctionNode { function_info }
+    pub fn new(function_info: FunctionInfo, returns: FunctionReturn, descriptor: FunctionDescriptor) -> Self {
+        FunctionNode {
+            function_info,
+            returns,
+            descriptor,
+            overload: None,
+            saved_signature: None,
+        }
+    }
+
+    /// Nodes of pure functions have no flow pins, so their data pins start at 0.
+    fn flow_pins(&self) -> usize {
+        if self.descriptor.pure {
+            0
+        } else {
+            1
+        }
+    }
+
+    pub fn is_flow_input(&self, input: usize) -> bool {
+        !self.descriptor.pure && input == 0
+    }
+
+    pub fn is_flow_output(&self, output: usize) -> bool {
+        !self.descriptor.pure && output == 0
+    }
+
+    /// The parameters in calling order.
+    pub fn args(&self) -> Vec<&ArgInfo> {
+        let mut args = self.function_info.args().iter().collect::<Vec<_>>();
+        args.sort_by_key(|arg| arg.index());
+        args
+    }
+
+    /// The input pin a parameter is wired to, pin 0 is the flow pin unless the node is pure.
+    pub fn input_pin(&self, arg: &ArgInfo) -> usize {
+        self.input_pin_at(arg.index())
+    }
+
+    pub fn input_pin_at(&self, index: usize) -> usize {
+        index + self.flow_pins()
+    }
+
+    /// The output pin of the `index`th returned value.
+    pub fn output_pin(&self, index: usize) -> usize {
+        index + self.flow_pins()
+    }
+
+    pub fn return_at_pin(&self, output: usize) -> Option<&str> {
+        self.returns
+            .types()
+            .get(output.checked_sub(self.flow_pins())?)
+            .copied()
+    }
+
+    pub fn input_pin_count(&self) -> usize {
+        self.function_info.arg_count() + self.flow_pins()
+    }
+
+    pub fn output_pin_count(&self) -> usize {
+        self.output_pin(self.returns.len())
+    }
+
+    pub fn arg_at_pin(&self, input: usize) -> Option<&ArgInfo> {
+        self.function_info
+            .args()
+            .iter()
+            .find(|arg| self.input_pin(arg) == input)
+    }
+
+    /// The stable id of the called function, see [`FunctionDescriptor::id`].
+    pub fn function_id(&self) -> &str {
+        self.descriptor
+            .id
+            .as_deref()
+            .or(self.function_info.name())
+            .unwrap_or_default()
+    }
+
+    pub fn arg_name(arg: &ArgInfo) -> String {
+        match arg.name() {
+            Some(name) => name.to_string(),
+            None => format!("arg{}", arg.index()),
+        }
     }
 }
+
+/// Stands in for a function node whose function isn't registered, so the node and its wires
+/// survive loading until it is migrated or removed.
+///
+/// The pins are whatever the saved wires and signature needed, the node doesn't compile.
+#[derive(Clone, Debug)]
+pub struct UnresolvedNode {
+    pub id: String,
+    pub version: u32,
+    pub overload: Option<String>,
+    pub signature: Option<SavedSignature>,
+    pub inputs: usize,
+    pub outputs: usize,
+}
+
+impl UnresolvedNode {
+    /// The overload set or function id the node was saved with.
+    pub fn name(&self) -> &str {
+        self.overload.as_deref().unwrap_or(&self.id)
+    }
+}
+
 #[derive(Debug)]
 pub struct TypeCreationNode {
     pub value: Box<dyn Reflect>,
@@ -142,6 +338,45 @@ impl TypeCreationNode {
     }
 }
 
+/// An exposed graph input, `value` is used unless the entity running the graph overrides
+/// `name` in its [`ScriptParameters`](crate::script_component::ScriptParameters).
+#[derive(Debug)]
+pub struct ParameterNode {
+    pub name: String,
+    pub value: Box<dyn Reflect>,
+}
+
+impl Clone for ParameterNode {
+    fn clone(&self) -> Self {
+        Self {
+            name: self.name.clone(),
+            value: self.value.clone_value(),
+        }
+    }
+}
+
+impl ParameterNode {
+    pub fn new(name: String, value: Box<dyn Reflect>) -> Self {
+        ParameterNode { name, value }
+    }
+
+    /// `parameter`, or `parameter_2`, `parameter_3`, ... if the graph already has one by that name.
+    pub fn unique_name(snarl: &Snarl<ScriptNode>) -> String {
+        let taken = |name: &str| {
+            snarl
+                .node_ids()
+                .any(|(_, node)| matches!(node, ScriptNode::Parameter(parameter_node) if parameter_node.name == name))
+        };
+        let mut name = "parameter".to_string();
+        let mut suffix = 1;
+        while taken(&name) {
+            suffix += 1;
+            name = format!("parameter_{}", suffix);
+        }
+        name
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct QueryNode {
     pub components: Vec<(String, ComponentId, TypeInfo)>,
@@ -161,13 +396,76 @@ pub(crate) struct Viewer<'a> {
     pub(crate) type_registry: Option<ResMut<'a, AppTypeRegistry>>,
     #[serde(skip)]
     pub(crate) component_map: Option<Res<'a, ComponentMap>>,
+    #[serde(skip)]
+    pub(crate) selection: Option<ResMut<'a, NodeSelection>>,
 }
 
-fn remove_before_double_colon(s: &str) -> String {
+/// The "Functions" menu, grouped by the categories of the function descriptors.
+#[derive(Default)]
+struct FunctionMenu<'a> {
+    categories: BTreeMap<&'a str, FunctionMenu<'a>>,
+    functions: Vec<(&'a String, &'a RegisteredFunction)>,
+}
+
+impl<'a> FunctionMenu<'a> {
+    fn insert(&mut self, category: &'a [String], name: &'a String, function: &'a RegisteredFunction) {
+        match category.split_first() {
+            None => self.functions.push((name, function)),
+            Some((first, rest)) => self
+                .categories
+                .entry(first.as_str())
+                .or_default()
+                .insert(rest, name, function),
+        }
+    }
+
+    /// Returns the name of the clicked function.
+    fn show(mut self, ui: &mut Ui) -> Option<&'a String> {
+        let mut clicked = None;
+        for (category, menu) in self.categories {
+            ui.menu_button(category, |ui| {
+                if let Some(name) = menu.show(ui) {
+                    clicked = Some(name);
+                }
+            });
+        }
+        self.functions.sort_by(|a, b| a.0.cmp(b.0));
+        for (name, function) in self.functions {
+            let tooltip = function.descriptor.tooltip();
+            let mut button = ui.button(name);
+            if !tooltip.is_empty() {
+                button = button.on_hover_text(tooltip);
+            }
+            if button.clicked() {
+                clicked = Some(name);
+            }
+        }
+        clicked
+    }
+}
+
+pub(crate) fn remove_before_double_colon(s: &str) -> String {
     s.rsplit("::").next().unwrap_or(s).to_string()
 }
 
 impl SnarlViewer<ScriptNode> for Viewer<'_> {
+    fn show_header(
+        &mut self,
+        node: NodeId,
+        inputs: &[InPin],
+        outputs: &[OutPin],
+        ui: &mut Ui,
+        scale: f32,
+        snarl: &mut Snarl<ScriptNode>,
+    ) {
+        let title = self.title(&snarl[node]);
+        if self.selection.as_ref().unwrap().0.contains(&node) {
+            ui.strong(format!("● {}", title));
+        } else {
+            ui.label(title);
+        }
+    }
+
     fn title(&mut self, node: &ScriptNode) -> String {
         match node {
             ScriptNode::Set(_set_node) => "set".to_string(),       //TODO
@@ -182,7 +480,10 @@ impl SnarlViewer<ScriptNode> for Viewer<'_> {
                 .reflect_type_ident()
                 .unwrap_or("unknown_type")
                 .to_string(),
+            ScriptNode::Parameter(parameter_node) => format!("parameter: {}", parameter_node.name),
             ScriptNode::Query(_query_node) => "query".to_string(), //TODO
+            ScriptNode::SelfEntity(_) => "self".to_string(),
+            ScriptNode::Unresolved(unresolved) => format!("{} (unregistered)", unresolved.name()),
         }
     }
 
@@ -190,9 +491,11 @@ impl SnarlViewer<ScriptNode> for Viewer<'_> {
         match node {
             ScriptNode::Set(_) => 1,                                          // the flow node
             ScriptNode::Field(_) => 1,                                        // just the data
-            ScriptNode::Function(_) => 2,                                     // data + flow
+            ScriptNode::Function(function_node) => function_node.output_pin_count(), // flow + data
             ScriptNode::TypeCreation(_) => 1,                                 // just the data
-            ScriptNode::Query(query_node) => 1 + query_node.components.len(), //plus flow
+            ScriptNode::Parameter(_) => 1,
+            ScriptNode::Query(query_node) | ScriptNode::SelfEntity(query_node) => 1 + query_node.components.len(), //plus flow
+            ScriptNode::Unresolved(unresolved) => unresolved.outputs,
         }
     }
 
@@ -200,7 +503,7 @@ impl SnarlViewer<ScriptNode> for Viewer<'_> {
         match node {
             ScriptNode::Set(_) => 3,   // the flow, the data, and the replacement,
             ScriptNode::Field(_) => 1, // just the input struct
-            ScriptNode::Function(function_node) => function_node.function_info.arg_count() + 1, // plus flow node
+            ScriptNode::Function(function_node) => function_node.input_pin_count(), // plus flow node unless pure
             ScriptNode::TypeCreation(type_creation_node) => {
                 match type_creation_node.value.reflect_ref() {
                     ReflectRef::Struct(dyn_struct) => {
@@ -210,7 +513,9 @@ impl SnarlViewer<ScriptNode> for Viewer<'_> {
                     _ => todo!(),
                 }
             }
-            ScriptNode::Query(_) => 0,
+            ScriptNode::Parameter(_) => 0, // edited in the body
+            ScriptNode::Query(_) | ScriptNode::SelfEntity(_) => 0,
+            ScriptNode::Unresolved(unresolved) => unresolved.inputs,
         }
     }
 
@@ -280,7 +585,8 @@ impl SnarlViewer<ScriptNode> for Viewer<'_> {
                         },
                     },
                     ScriptNode::Function(_) => todo!(),
-                    ScriptNode::TypeCreation(type_creation) => match type_creation.value.reflect_ref() {
+                    ScriptNode::TypeCreation(TypeCreationNode { value })
+                    | ScriptNode::Parameter(ParameterNode { value, .. }) => match value.reflect_ref() {
                         ReflectRef::Struct(dyn_struct) => {
                             for (index, f) in dyn_struct.iter_fields().enumerate() {
                                 fields.push(TypeInfoWrapper(
@@ -296,7 +602,7 @@ impl SnarlViewer<ScriptNode> for Viewer<'_> {
                         }
                         _ => todo!(),
                     },
-                    ScriptNode::Query(query_node) => {
+                    ScriptNode::Query(query_node) | ScriptNode::SelfEntity(query_node) => {
                         let (name, id, type_info) =
                             query_node.components.get(first.output - 1).unwrap();
                         match type_info.clone() {
@@ -361,9 +667,21 @@ impl SnarlViewer<ScriptNode> for Viewer<'_> {
                     });
                 PinInfo::circle().with_fill(color)
             }
-            ScriptNode::Function(_) => if pin.id.input == 0 {
+            ScriptNode::Function(function_node) => if function_node.is_flow_input(pin.id.input) {
                 PinInfo::triangle()
             } else {
+                if let Some(arg) = function_node.arg_at_pin(pin.id.input) {
+                    if function_node.overload.is_some() {
+                        // the type depends on the overload that gets picked
+                        ui.label(FunctionNode::arg_name(arg));
+                    } else {
+                        ui.label(format!(
+                            "{}: {}",
+                            FunctionNode::arg_name(arg),
+                            remove_before_double_colon(arg.type_path())
+                        ));
+                    }
+                }
                 PinInfo::circle()
             }
             .with_fill(color),
@@ -391,7 +709,9 @@ impl SnarlViewer<ScriptNode> for Viewer<'_> {
                 }
                 PinInfo::circle().with_fill(color)
             }
-            ScriptNode::Query(_) => unreachable!(), //no inputs for queries
+            ScriptNode::Parameter(_) => unreachable!(),
+            ScriptNode::Query(_) | ScriptNode::SelfEntity(_) => unreachable!(), //no inputs for queries
+            ScriptNode::Unresolved(_) => PinInfo::circle().with_fill(color),
         }
     }
 
@@ -413,27 +733,37 @@ impl SnarlViewer<ScriptNode> for Viewer<'_> {
                 PinInfo::triangle().with_fill(color) // only flow node output
             }
             ScriptNode::Field(_) => PinInfo::circle().with_fill(color),
-            ScriptNode::Function(_) => if pin.id.output == 0 {
+            ScriptNode::Function(function_node) => if function_node.is_flow_output(pin.id.output) {
                 PinInfo::triangle()
             } else {
+                if let Some(type_path) = function_node.return_at_pin(pin.id.output) {
+                    if function_node.overload.is_none() {
+                        ui.label(remove_before_double_colon(type_path));
+                    }
+                }
                 PinInfo::circle()
             }
             .with_fill(color),
             ScriptNode::TypeCreation(_) => PinInfo::circle().with_fill(color), // no flow nodes just a single data
-            ScriptNode::Query(query_node) => if pin.id.output == 0 {
+            ScriptNode::Parameter(parameter_node) => {
+                ui.label(parameter_node.value.reflect_type_ident().unwrap_or("unknown"));
+                PinInfo::circle().with_fill(color)
+            }
+            ScriptNode::Query(query_node) | ScriptNode::SelfEntity(query_node) => if pin.id.output == 0 {
                 PinInfo::triangle()
             } else {
                 ui.label(&query_node.components.get(pin.id.output - 1).unwrap().0);
                 PinInfo::circle()
             }
             .with_fill(color),
+            ScriptNode::Unresolved(_) => PinInfo::circle().with_fill(color),
         }
     }
 
     #[inline]
     fn has_body(&mut self, node: &ScriptNode) -> bool {
         match node {
-            ScriptNode::Query(_) | ScriptNode::TypeCreation(_) => true,
+            ScriptNode::Query(_) | ScriptNode::SelfEntity(_) | ScriptNode::TypeCreation(_) | ScriptNode::Parameter(_) => true,
             _ => false,
         }
     }
@@ -454,7 +784,16 @@ impl SnarlViewer<ScriptNode> for Viewer<'_> {
             ScriptNode::Field(_) => {}
             ScriptNode::Function(_) => {}
             ScriptNode::TypeCreation(_) => {}
-            ScriptNode::Query(query) => {
+            ScriptNode::Unresolved(_) => {}
+            ScriptNode::Parameter(parameter) => {
+                ui.text_edit_singleline(&mut parameter.name);
+                bevy_inspector_egui::reflect_inspector::ui_for_value(
+                    parameter.value.as_mut(),
+                    ui,
+                    &self.type_registry.as_ref().unwrap().read(),
+                );
+            }
+            ScriptNode::Query(query) | ScriptNode::SelfEntity(query) => {
                 ui.menu_button("Add Component", |ui| {
                     for ty in self.type_registry.as_mut().unwrap().read().iter() {
                         let name = remove_before_double_colon(ty.type_info().type_path());
@@ -466,7 +805,7 @@ impl SnarlViewer<ScriptNode> for Viewer<'_> {
                             None => continue,
                             Some(this) => {
                                 if ui.button(name.clone()).clicked() {
-                                    query.components.push((name, *this, ty.type_info().clone()));
+                                    query.components.push((name, this.id, ty.type_info().clone()));
                                     ui.close_menu();
                                 }
                             }
@@ -482,10 +821,18 @@ impl SnarlViewer<ScriptNode> for Viewer<'_> {
     }
 
     fn show_graph_menu(&mut self, pos: Pos2, ui: &mut Ui, scale: f32, snarl: &mut Snarl<ScriptNode>) {
+        if !self.selection.as_ref().unwrap().0.is_empty() && ui.button("clear selection").clicked() {
+            self.selection.as_mut().unwrap().0.clear();
+            ui.close_menu();
+        }
         if ui.button("Query").clicked() {
             snarl.insert_node(pos, ScriptNode::query());
             ui.close_menu();
         }
+        if ui.button("Self").clicked() {
+            snarl.insert_node(pos, ScriptNode::self_entity());
+            ui.close_menu();
+        }
         if ui.button("Set").clicked() {
             snarl.insert_node(pos, ScriptNode::set());
             ui.close_menu();
@@ -496,42 +843,65 @@ impl SnarlViewer<ScriptNode> for Viewer<'_> {
         }
         ui.menu_button("Functions", |ui| {
             ScrollArea::both().show(ui, |ui| {
-                for (s, f) in self.function_registry.unwrap().0.iter() {
-                    if ui.button(s).clicked() {
-                        snarl.insert_node(
-                            pos,
-                            ScriptNode::function(f.info().clone().with_name(s.clone())),
-                        );
-                        ui.close_menu();
+                let function_registry = self.function_registry.unwrap();
+                let mut menu = FunctionMenu::default();
+                for (name, function) in function_registry.functions.iter() {
+                    // only the overload set shows up
+                    if function_registry.is_overload_member(name) {
+                        continue;
                     }
+                    menu.insert(&function.descriptor.category, name, function);
+                }
+                for (overload, members) in function_registry.overloads.iter() {
+                    let first = function_registry.functions.get(&members[0]).unwrap();
+                    menu.insert(&first.descriptor.category, overload, first);
+                }
+                if let Some(name) = menu.show(ui) {
+                    let node = ScriptNode::registered_function(
+                        name,
+                        function_registry,
+                        &self.type_registry.as_ref().unwrap().read(),
+                    )
+                    .unwrap();
+                    snarl.insert_node(pos, node);
+                    ui.close_menu();
                 }
             });
         });
-        ui.menu_button("Type Creation", |ui| {
-            ScrollArea::both().show(ui, |ui| {
-                let mut nodes = vec![];
-                let binding = self.type_registry.as_ref().unwrap().read();
-                let binding2 = binding.iter();
-                for ty in binding2 {
-                    let Some(default) = ty.data::<ReflectDefault>() else {
-                        continue;
-                    };
-                    match ty.type_info() {
-                        TypeInfo::Struct(_) | TypeInfo::Value(_) => {}
-                        _ => continue,
+        for (menu, make_node) in [
+            ("Type Creation", ScriptNode::type_creation as fn(Box<dyn Reflect>) -> ScriptNode),
+            ("Parameter", ScriptNode::parameter),
+        ] {
+            ui.menu_button(menu, |ui| {
+                ScrollArea::both().show(ui, |ui| {
+                    let mut nodes = vec![];
+                    let binding = self.type_registry.as_ref().unwrap().read();
+                    let binding2 = binding.iter();
+                    for ty in binding2 {
+                        let Some(default) = ty.data::<ReflectDefault>() else {
+                            continue;
+                        };
+                        match ty.type_info() {
+                            TypeInfo::Struct(_) | TypeInfo::Value(_) => {}
+                            _ => continue,
+                        }
+                        let name = remove_before_double_colon(ty.type_info().type_path());
+                        nodes.push((name, default));
                     }
-                    let name = remove_before_double_colon(ty.type_info().type_path());
-                    nodes.push((name, default));
-                }
-                nodes.sort_by(|a, b| a.0.cmp(&b.0));
-                for (name, default) in nodes {
-                    if ui.button(name).clicked() {
-                        snarl.insert_node(pos, ScriptNode::type_creation(default.default()));
-                        ui.close_menu();
+                    nodes.sort_by(|a, b| a.0.cmp(&b.0));
+                    for (name, default) in nodes {
+                        if ui.button(name).clicked() {
+                            let mut node = make_node(default.default());
+                            if let ScriptNode::Parameter(parameter_node) = &mut node {
+                                parameter_node.name = ParameterNode::unique_name(snarl);
+                            }
+                            snarl.insert_node(pos, node);
+                            ui.close_menu();
+                        }
                     }
-                }
+                });
             });
-        });
+        }
     }
 
     fn has_node_menu(&mut self, node: &ScriptNode) -> bool {
@@ -547,7 +917,25 @@ impl SnarlViewer<ScriptNode> for Viewer<'_> {
         scale: f32,
         snarl: &mut Snarl<ScriptNode>,
     ) {
+        let selection = self.selection.as_mut().unwrap();
+        if selection.0.contains(&node) {
+            if ui.button("deselect").clicked() {
+                selection.0.remove(&node);
+                ui.close_menu();
+            }
+        } else if ui.button("select").clicked() {
+            selection.0.insert(node);
+            ui.close_menu();
+        }
+        if !selection.0.is_empty() && ui.button("copy selection").clicked() {
+            match copy_selection(snarl, selection, &self.type_registry.as_ref().unwrap().read()) {
+                Ok(text) => ui.ctx().output_mut(|output| output.copied_text = text),
+                Err(err) => println!("couldn't copy selection: {}", err),
+            }
+            ui.close_menu();
+        }
         if ui.button("delete").clicked() {
+            self.selection.as_mut().unwrap().0.remove(&node);
             snarl.remove_node(node);
             ui.close_menu();
         }
@@ -0,0 +1,29 @@
+//! Fixtures shared by the test modules of the crate.
+
+use std::any::TypeId;
+use bevy::prelude::{AppTypeRegistry, Component, Reflect, World};
+use crate::registry::{ComponentEntry, ComponentMap};
+
+#[derive(Component, Reflect, Default, Debug, PartialEq)]
+pub(crate) struct Position {
+    pub(crate) x: f32,
+    pub(crate) y: f32,
+}
+
+pub(crate) fn sub(a: f32, b: f32) -> f32 {
+    a - b
+}
+
+/// A world with `Position` in its type registry and the component map scripts find it in.
+pub(crate) fn position_world() -> (World, ComponentMap) {
+    let mut world = World::new();
+    world.init_resource::<AppTypeRegistry>();
+    world.resource::<AppTypeRegistry>().write().register::<Position>();
+    let component_id = world.init_component::<Position>();
+    let mut component_map = ComponentMap::default();
+    component_map.0.insert(TypeId::of::<Position>(), ComponentEntry {
+        id: component_id,
+        reflect_component: false,
+    });
+    (world, component_map)
+}
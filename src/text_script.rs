@@ -0,0 +1,1019 @@
+//! A text syntax for graphs.
+//!
+//! ```text
+//! query (transform: Transform) {
+//!     param speed: f32 = 2.0
+//!     let up = Vec3(0.0, 1.0, 0.0)
+//!     transform.translation = add_vec3(transform.translation, up)
+//! }
+//! ```
+//!
+//! The root is `query (...)` or `self (...)` with a binding per component. Statements are
+//! assignments (a set node), calls (a function node on the flow), `let` to name a value and
+//! `param` for an exposed parameter. Calls to registered functions are function nodes, calls to
+//! types with `ReflectDefault` are type creation nodes taking literals. `_` leaves a pin unwired,
+//! the compiler reports it until something is wired into it.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter, Write};
+use bevy::prelude::ReflectDefault;
+use bevy::reflect::{Reflect, ReflectMut, ReflectRef, TypeInfo, TypeRegistration, TypeRegistry};
+use bevy_egui::egui::Pos2;
+use egui_snarl::{InPinId, NodeId, OutPinId, Snarl};
+use crate::compiler::strip_reference;
+use crate::registry::{ComponentMap, FunctionRegistry};
+use crate::scripting::{remove_before_double_colon, FieldNode, FunctionNode, FunctionReturn, ParameterNode, QueryNode, ScriptNode, SetNode, TypeCreationNode, UnresolvedNode};
+
+#[derive(Debug)]
+pub enum TextScriptError {
+    Parse {
+        line: usize,
+        message: String,
+    },
+    UnknownName(String),
+    UnknownType(String),
+    UnknownComponent(String),
+    UnknownField {
+        type_path: String,
+        field: String,
+    },
+    /// A value or call that doesn't fit where it's used.
+    Invalid(String),
+}
+
+impl Display for TextScriptError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TextScriptError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+            TextScriptError::UnknownName(name) => write!(f, "`{}` is neither a binding, a function nor a type", name),
+            TextScriptError::UnknownType(name) => write!(f, "no type named `{}` is registered", name),
+            TextScriptError::UnknownComponent(name) => write!(f, "`{}` is not a known component", name),
+            TextScriptError::UnknownField { type_path, field } => write!(f, "`{}` has no field `{}`", type_path, field),
+            TextScriptError::Invalid(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for TextScriptError {}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Ident(String),
+    Number(String),
+    Str(String),
+    /// `::`
+    PathSep,
+    Punct(char),
+}
+
+fn lex(source: &str) -> Result<Vec<(Token, usize)>, TextScriptError> {
+    let mut tokens = vec![];
+    let mut chars = source.chars().peekable();
+    let mut line = 1;
+    while let Some(&c) = chars.peek() {
+        match c {
+            '\n' => {
+                line += 1;
+                chars.next();
+            }
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '/' => {
+                chars.next();
+                if chars.peek() != Some(&'/') {
+                    return Err(TextScriptError::Parse { line, message: "expected a `//` comment".to_string() });
+                }
+                while chars.peek().is_some_and(|c| *c != '\n') {
+                    chars.next();
+                }
+            }
+            '"' => {
+                chars.next();
+                let mut string = String::new();
+                loop {
+                    match chars.next() {
+                        None => return Err(TextScriptError::Parse { line, message: "unterminated string".to_string() }),
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some('n') => string.push('\n'),
+                            Some(c) => string.push(c),
+                            None => return Err(TextScriptError::Parse { line, message: "unterminated string".to_string() }),
+                        },
+                        Some(c) => string.push(c),
+                    }
+                }
+                tokens.push((Token::Str(string), line));
+            }
+            ':' => {
+                chars.next();
+                if chars.peek() == Some(&':') {
+                    chars.next();
+                    tokens.push((Token::PathSep, line));
+                } else {
+                    tokens.push((Token::Punct(':'), line));
+                }
+            }
+            // there are no operators, a `-` is only ever the sign of a number
+            c if c.is_ascii_digit() || (c == '-' && chars.clone().nth(1).is_some_and(|c| c.is_ascii_digit())) => {
+                // after a `.` it's a tuple index, `v.0.1` is two of them and not a float
+                let index = tokens.last().is_some_and(|(token, _)| *token == Token::Punct('.'));
+                let mut number = String::new();
+                number.push(c);
+                chars.next();
+                while let Some(&c) = chars.peek() {
+                    let exponent_sign = (c == '-' || c == '+') && number.ends_with(['e', 'E']);
+                    let part = if index {
+                        c.is_ascii_digit()
+                    } else {
+                        c.is_ascii_alphanumeric() || c == '.' || c == '_' || exponent_sign
+                    };
+                    if part {
+                        number.push(c);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                tokens.push((Token::Number(number), line));
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let mut ident = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_alphanumeric() || c == '_' {
+                        ident.push(c);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                tokens.push((Token::Ident(ident), line));
+            }
+            '(' | ')' | '{' | '}' | ',' | '.' | '=' | ';' => {
+                chars.next();
+                tokens.push((Token::Punct(c), line));
+            }
+            c => return Err(TextScriptError::Parse { line, message: format!("unexpected `{}`", c) }),
+        }
+    }
+    Ok(tokens)
+}
+
+#[derive(Clone, Debug)]
+enum Literal {
+    Number(String),
+    Str(String),
+    Bool(bool),
+}
+
+#[derive(Clone, Debug)]
+enum Expr {
+    Literal(Literal),
+    /// `_`, an unwired pin.
+    Hole,
+    Var(String),
+    Field(Box<Expr>, String),
+    Call(String, Vec<Expr>),
+}
+
+#[derive(Debug)]
+enum Statement {
+    Assign(Expr, Expr),
+    Call(Expr),
+    Let(String, Expr),
+    Param {
+        name: String,
+        type_name: String,
+        value: Expr,
+    },
+}
+
+#[derive(Debug)]
+struct Script {
+    self_entity: bool,
+    bindings: Vec<(String, String)>,
+    statements: Vec<Statement>,
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position).map(|(token, _)| token)
+    }
+
+    fn line(&self) -> usize {
+        self.tokens
+            .get(self.position)
+            .or(self.tokens.last())
+            .map(|(_, line)| *line)
+            .unwrap_or(1)
+    }
+
+    fn error<T>(&self, message: impl Into<String>) -> Result<T, TextScriptError> {
+        Err(TextScriptError::Parse {
+            line: self.line(),
+            message: message.into(),
+        })
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.peek().cloned();
+        self.position += 1;
+        token
+    }
+
+    fn eat(&mut self, punct: char) -> bool {
+        if self.peek() == Some(&Token::Punct(punct)) {
+            self.position += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, punct: char) -> Result<(), TextScriptError> {
+        if self.eat(punct) {
+            Ok(())
+        } else {
+            self.error(format!("expected `{}`", punct))
+        }
+    }
+
+    fn ident(&mut self) -> Result<String, TextScriptError> {
+        match self.next() {
+            Some(Token::Ident(ident)) => Ok(ident),
+            _ => {
+                self.position -= 1;
+                self.error("expected a name")
+            }
+        }
+    }
+
+    fn script(&mut self) -> Result<Script, TextScriptError> {
+        let self_entity = match self.ident()?.as_str() {
+            "query" => false,
+            "self" => true,
+            _ => return self.error("scripts start with `query` or `self`"),
+        };
+        self.expect('(')?;
+        let mut bindings = vec![];
+        while !self.eat(')') {
+            let name = self.ident()?;
+            self.expect(':')?;
+            bindings.push((name, self.ident()?));
+            if !self.eat(',') {
+                self.expect(')')?;
+                break;
+            }
+        }
+        self.expect('{')?;
+        let mut statements = vec![];
+        while !self.eat('}') {
+            if self.peek().is_none() {
+                return self.error("expected `}`");
+            }
+            statements.push(self.statement()?);
+            self.eat(';');
+        }
+        if self.peek().is_some() {
+            return self.error("expected the end of the script");
+        }
+        Ok(Script {
+            self_entity,
+            bindings,
+            statements,
+        })
+    }
+
+    fn statement(&mut self) -> Result<Statement, TextScriptError> {
+        match self.peek() {
+            Some(Token::Ident(keyword)) if keyword == "let" => {
+                self.next();
+                let name = self.ident()?;
+                self.expect('=')?;
+                Ok(Statement::Let(name, self.expr()?))
+            }
+            Some(Token::Ident(keyword)) if keyword == "param" => {
+                self.next();
+                let name = self.ident()?;
+                self.expect(':')?;
+                let type_name = self.ident()?;
+                self.expect('=')?;
+                Ok(Statement::Param {
+                    name,
+                    type_name,
+                    value: self.expr()?,
+                })
+            }
+            _ => {
+                let expr = self.expr()?;
+                if self.eat('=') {
+                    return Ok(Statement::Assign(expr, self.expr()?));
+                }
+                match expr {
+                    Expr::Call(..) => Ok(Statement::Call(expr)),
+                    _ => self.error("expected an assignment or a call"),
+                }
+            }
+        }
+    }
+
+    fn expr(&mut self) -> Result<Expr, TextScriptError> {
+        let mut expr = match self.next() {
+            Some(Token::Number(number)) => Expr::Literal(Literal::Number(number)),
+            Some(Token::Str(string)) => Expr::Literal(Literal::Str(string)),
+            Some(Token::Ident(ident)) if ident == "true" => Expr::Literal(Literal::Bool(true)),
+            Some(Token::Ident(ident)) if ident == "false" => Expr::Literal(Literal::Bool(false)),
+            Some(Token::Ident(ident)) if ident == "_" => Expr::Hole,
+            Some(Token::Ident(mut path)) => {
+                while self.peek() == Some(&Token::PathSep) {
+                    self.next();
+                    path.push_str("::");
+                    path.push_str(&self.ident()?);
+                }
+                if self.eat('(') {
+                    let mut args = vec![];
+                    while !self.eat(')') {
+                        args.push(self.expr()?);
+                        if !self.eat(',') {
+                            self.expect(')')?;
+                            break;
+                        }
+                    }
+                    Expr::Call(path, args)
+                } else if float_constant(&path).is_some() {
+                    Expr::Literal(Literal::Number(path))
+                } else {
+                    Expr::Var(path)
+                }
+            }
+            _ => {
+                self.position -= 1;
+                return self.error("expected a value");
+            }
+        };
+        while self.eat('.') {
+            let field = match self.next() {
+                Some(Token::Ident(field)) | Some(Token::Number(field)) => field,
+                _ => return self.error("expected a field name"),
+            };
+            expr = Expr::Field(Box::new(expr), field);
+        }
+        Ok(expr)
+    }
+}
+
+/// A value in scope while building the graph.
+#[derive(Clone)]
+enum Built {
+    Pin {
+        pin: OutPinId,
+        type_path: String,
+    },
+    /// A call returning a tuple, `.0` picks an element.
+    Tuple(Vec<(OutPinId, String)>),
+    Unit,
+}
+
+struct Builder<'a> {
+    snarl: Snarl<ScriptNode>,
+    function_registry: &'a FunctionRegistry,
+    type_registry: &'a TypeRegistry,
+    scope: HashMap<String, Built>,
+    last_flow: OutPinId,
+    column: usize,
+    row: usize,
+}
+
+/// Parses a script into a graph, nodes are laid out in a column per statement.
+pub fn parse_script(
+    source: &str,
+    function_registry: &FunctionRegistry,
+    type_registry: &TypeRegistry,
+    component_map: &ComponentMap,
+) -> Result<Snarl<ScriptNode>, TextScriptError> {
+    let script = Parser {
+        tokens: lex(source)?,
+        position: 0,
+    }
+    .script()?;
+
+    let mut query_node = QueryNode::new();
+    for (_, type_name) in &script.bindings {
+        let registration = find_type(type_registry, type_name)?;
+        let entry = component_map
+            .0
+            .get(&registration.type_id())
+            .ok_or_else(|| TextScriptError::UnknownComponent(type_name.clone()))?;
+        query_node.components.push((
+            remove_before_double_colon(registration.type_info().type_path()),
+            entry.id,
+            registration.type_info().clone(),
+        ));
+    }
+    let components = query_node.components.clone();
+    let root = if script.self_entity {
+        ScriptNode::SelfEntity(query_node)
+    } else {
+        ScriptNode::Query(query_node)
+    };
+    let mut snarl = Snarl::new();
+    let root = snarl.insert_node(Pos2::ZERO, root);
+
+    let mut builder = Builder {
+        snarl,
+        function_registry,
+        type_registry,
+        scope: HashMap::new(),
+        last_flow: OutPinId { node: root, output: 0 },
+        column: 0,
+        row: 0,
+    };
+    for (index, ((name, _), (_, _, type_info))) in script.bindings.iter().zip(&components).enumerate() {
+        let type_path = type_info.type_path().to_string();
+        builder.scope.insert(name.clone(), Built::Pin {
+            pin: OutPinId {
+                node: root,
+                output: index + 1,
+            },
+            type_path,
+        });
+    }
+    for statement in script.statements {
+        builder.column += 1;
+        builder.row = 0;
+        builder.statement(statement)?;
+    }
+    Ok(builder.snarl)
+}
+
+impl Builder<'_> {
+    fn insert(&mut self, node: ScriptNode) -> NodeId {
+        let pos = Pos2::new(self.column as f32 * 260.0, self.row as f32 * 140.0);
+        self.row += 1;
+        self.snarl.insert_node(pos, node)
+    }
+
+    fn flow(&mut self, node: NodeId) {
+        self.snarl.connect(self.last_flow, InPinId { node, input: 0 });
+        self.last_flow = OutPinId { node, output: 0 };
+    }
+
+    fn statement(&mut self, statement: Statement) -> Result<(), TextScriptError> {
+        match statement {
+            Statement::Assign(target, value) => {
+                let Built::Pin { pin: target, type_path } = self.expr(&target)? else {
+                    return Err(TextScriptError::Invalid("only single values can be assigned to".to_string()));
+                };
+                let value = self.value(&value, Some(&type_path))?;
+                let set = self.insert(ScriptNode::Set(SetNode::new()));
+                self.flow(set);
+                self.snarl.connect(target, InPinId { node: set, input: 1 });
+                if let Some(value) = value {
+                    self.snarl.connect(value, InPinId { node: set, input: 2 });
+                }
+            }
+            // impure calls are put on the flow when they are built
+            Statement::Call(call) => {
+                self.expr(&call)?;
+            }
+            Statement::Let(name, value) => {
+                let built = match &value {
+                    Expr::Literal(literal) => {
+                        let value = literal_value(literal, None)?;
+                        let type_path = value.reflect_type_path().to_string();
+                        let node = self.insert(ScriptNode::TypeCreation(TypeCreationNode::new(value)));
+                        Built::Pin {
+                            pin: OutPinId { node, output: 0 },
+                            type_path,
+                        }
+                    }
+                    _ => self.expr(&value)?,
+                };
+                self.scope.insert(name, built);
+            }
+            Statement::Param { name, type_name, value } => {
+                let type_path = find_type(self.type_registry, &type_name)?.type_info().type_path().to_string();
+                let value = self.construct(&value, &type_path)?;
+                let node = self.insert(ScriptNode::Parameter(ParameterNode::new(name.clone(), value)));
+                self.scope.insert(name, Built::Pin {
+                    pin: OutPinId { node, output: 0 },
+                    type_path,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// A single value, literals become type creation nodes of `type_path`.
+    fn value(&mut self, expr: &Expr, type_path: Option<&str>) -> Result<Option<OutPinId>, TextScriptError> {
+        match expr {
+            Expr::Hole => Ok(None),
+            Expr::Literal(literal) => {
+                let value = literal_value(literal, type_path)?;
+                let node = self.insert(ScriptNode::TypeCreation(TypeCreationNode::new(value)));
+                Ok(Some(OutPinId { node, output: 0 }))
+            }
+            _ => match self.expr(expr)? {
+                Built::Pin { pin, .. } => Ok(Some(pin)),
+                _ => Err(TextScriptError::Invalid(format!("{:?} isn't a single value", expr))),
+            },
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) -> Result<Built, TextScriptError> {
+        match expr {
+            Expr::Literal(_) | Expr::Hole => Err(TextScriptError::Invalid(format!("{:?} needs a type here", expr))),
+            Expr::Var(name) => self
+                .scope
+                .get(name)
+                .cloned()
+                .ok_or_else(|| TextScriptError::UnknownName(name.clone())),
+            Expr::Field(base, field) => match self.expr(base)? {
+                Built::Tuple(elements) => field
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|index| elements.get(index))
+                    .map(|(pin, type_path)| Built::Pin {
+                        pin: *pin,
+                        type_path: type_path.clone(),
+                    })
+                    .ok_or_else(|| TextScriptError::Invalid(format!("the tuple has no element `{}`", field))),
+                Built::Pin { pin, type_path } => {
+                    let field_info = self.field_type(&type_path, field)?;
+                    let field_type_path = field_info.type_path().to_string();
+                    let mut field_node = FieldNode::new();
+                    field_node.field = Some(field_info);
+                    field_node.name = Some(field.clone());
+                    let node = self.insert(ScriptNode::Field(field_node));
+                    self.snarl.connect(pin, InPinId { node, input: 0 });
+                    Ok(Built::Pin {
+                        pin: OutPinId { node, output: 0 },
+                        type_path: field_type_path,
+                    })
+                }
+                Built::Unit => Err(TextScriptError::Invalid(format!("can't take `.{}` of nothing", field))),
+            },
+            Expr::Call(name, args) => {
+                let function_name = match self.function_registry.overloads.contains_key(name) {
+                    true => Some(name.as_str()),
+                    false => self.function_registry.resolve(name).or_else(|| {
+                        self.function_registry
+                            .functions
+                            .get_key_value(name)
+                            .map(|(name, _)| name.as_str())
+                    }),
+                };
+                match function_name {
+                    Some(function_name) => self.call(function_name.to_string(), args),
+                    None => {
+                        let type_path = find_type(self.type_registry, name)?.type_info().type_path().to_string();
+                        let value = self.construct(expr, &type_path)?;
+                        let node = self.insert(ScriptNode::TypeCreation(TypeCreationNode::new(value)));
+                        Ok(Built::Pin {
+                            pin: OutPinId { node, output: 0 },
+                            type_path,
+                        })
+                    }
+                }
+            }
+        }
+    }
+
+    fn call(&mut self, name: String, args: &[Expr]) -> Result<Built, TextScriptError> {
+        let Some(ScriptNode::Function(function_node)) = ScriptNode::registered_function(&name, self.function_registry, self.type_registry) else {
+            return Err(TextScriptError::UnknownName(name));
+        };
+        if args.len() != function_node.function_info.arg_count() {
+            return Err(TextScriptError::Invalid(format!(
+                "`{}` takes {} arguments, not {}",
+                name,
+                function_node.function_info.arg_count(),
+                args.len()
+            )));
+        }
+        let mut pins = vec![];
+        for arg in function_node.args() {
+            // the overload is picked by the compiler, so literals keep their own type
+            let type_path = match function_node.overload {
+                Some(_) => None,
+                None => Some(strip_reference(arg.type_path())),
+            };
+            pins.push((function_node.input_pin(arg), self.value(&args[arg.index()], type_path)?));
+        }
+        let node = self.insert(ScriptNode::Function(function_node.clone()));
+        for (input, pin) in pins {
+            if let Some(pin) = pin {
+                self.snarl.connect(pin, InPinId { node, input });
+            }
+        }
+        if !function_node.descriptor.pure {
+            self.flow(node);
+        }
+        Ok(match &function_node.returns {
+            FunctionReturn::Unit => Built::Unit,
+            FunctionReturn::Single(type_path) => Built::Pin {
+                pin: OutPinId {
+                    node,
+                    output: function_node.output_pin(0),
+                },
+                type_path: type_path.clone(),
+            },
+            FunctionReturn::Tuple(elements) => Built::Tuple(
+                elements
+                    .iter()
+                    .enumerate()
+                    .map(|(index, type_path)| {
+                        (
+                            OutPinId {
+                                node,
+                                output: function_node.output_pin(index),
+                            },
+                            type_path.clone(),
+                        )
+                    })
+                    .collect(),
+            ),
+        })
+    }
+
+    /// The value of a literal or a constructor call like `Vec3(0.0, 1.0, 0.0)`.
+    fn construct(&self, expr: &Expr, type_path: &str) -> Result<Box<dyn Reflect>, TextScriptError> {
+        let args = match expr {
+            Expr::Literal(literal) => return literal_value(literal, Some(type_path)),
+            Expr::Call(_, args) => args,
+            _ => return Err(TextScriptError::Invalid(format!("`{}` values can only be made from literals", type_path))),
+        };
+        let registration = self
+            .type_registry
+            .get_with_type_path(type_path)
+            .ok_or_else(|| TextScriptError::UnknownType(type_path.to_string()))?;
+        if let (TypeInfo::Value(_), [arg]) = (registration.type_info(), args.as_slice()) {
+            return self.construct(arg, type_path);
+        }
+        let default = registration
+            .data::<ReflectDefault>()
+            .ok_or_else(|| TextScriptError::Invalid(format!("`{}` can't be created", type_path)))?;
+        let mut value = default.default();
+        for (index, arg) in args.iter().enumerate() {
+            if let Expr::Hole = arg {
+                continue;
+            }
+            let field = match value.reflect_mut() {
+                ReflectMut::Struct(dyn_struct) => dyn_struct.field_at_mut(index),
+                ReflectMut::TupleStruct(tuple_struct) => tuple_struct.field_mut(index),
+                _ => None,
+            }
+            .ok_or_else(|| TextScriptError::Invalid(format!("`{}` has no field {}", type_path, index)))?;
+            let field_type_path = field.reflect_type_path().to_string();
+            field.apply(self.construct(arg, &field_type_path)?.as_ref());
+        }
+        Ok(value)
+    }
+
+    fn field_type(&self, type_path: &str, field: &str) -> Result<TypeInfo, TextScriptError> {
+        let unknown_field = || TextScriptError::UnknownField {
+            type_path: type_path.to_string(),
+            field: field.to_string(),
+        };
+        let registration = self
+            .type_registry
+            .get_with_type_path(type_path)
+            .ok_or_else(|| TextScriptError::UnknownType(type_path.to_string()))?;
+        let type_id = match registration.type_info() {
+            TypeInfo::Struct(struct_info) => struct_info.field(field).map(|field| field.type_id()),
+            TypeInfo::TupleStruct(tuple_struct_info) => field
+                .parse()
+                .ok()
+                .and_then(|index| tuple_struct_info.field_at(index))
+                .map(|field| field.type_id()),
+            _ => None,
+        }
+        .ok_or_else(unknown_field)?;
+        self.type_registry
+            .get(type_id)
+            .map(|registration| registration.type_info().clone())
+            .ok_or_else(unknown_field)
+    }
+}
+
+/// Looks a type up by its short path, e.g. `Transform`.
+fn find_type<'r>(type_registry: &'r TypeRegistry, name: &str) -> Result<&'r TypeRegistration, TextScriptError> {
+    type_registry
+        .iter()
+        .find(|registration| registration.type_info().type_path_table().short_path() == name)
+        .ok_or_else(|| TextScriptError::UnknownType(name.to_string()))
+}
+
+/// Numbers without a type are `f32` if they have a fraction and `i32` otherwise.
+fn literal_value(literal: &Literal, type_path: Option<&str>) -> Result<Box<dyn Reflect>, TextScriptError> {
+    let invalid = || TextScriptError::Invalid(format!("{:?} isn't a `{}`", literal, type_path.unwrap_or("value")));
+    match literal {
+        Literal::Number(number) => {
+            let (number, type_path) = match float_constant(number) {
+                Some((float_type, _)) if type_path.is_some_and(|type_path| type_path != float_type) => return Err(invalid()),
+                Some((float_type, text)) => (text, float_type),
+                None => (number.as_str(), type_path.unwrap_or(if number.contains(['.', 'e', 'E']) { "f32" } else { "i32" })),
+            };
+            macro_rules! numbers {
+                ($($ty:ty),*) => {
+                    match type_path {
+                        $(stringify!($ty) => number.parse::<$ty>().map(|a| Box::new(a) as Box<dyn Reflect>).map_err(|_| invalid()),)*
+                        _ => Err(invalid()),
+                    }
+                };
+            }
+            numbers!(f32, f64, i8, i16, i32, i64, u8, u16, u32, u64, isize, usize)
+        }
+        Literal::Str(string) => match type_path {
+            None | Some("alloc::string::String") => Ok(Box::new(string.clone())),
+            _ => Err(invalid()),
+        },
+        Literal::Bool(boolean) => match type_path {
+            None | Some("bool") => Ok(Box::new(*boolean)),
+            _ => Err(invalid()),
+        },
+    }
+}
+
+/// `f32::NAN` and the infinities of `f32` and `f64` by their type and how `parse` spells them,
+/// non-finite floats are printed like that.
+fn float_constant(path: &str) -> Option<(&str, &'static str)> {
+    let (float_type, name) = path.split_once("::")?;
+    if float_type != "f32" && float_type != "f64" {
+        return None;
+    }
+    let text = match name {
+        "NAN" => "NaN",
+        "INFINITY" => "inf",
+        "NEG_INFINITY" => "-inf",
+        _ => return None,
+    };
+    Some((float_type, text))
+}
+
+/// Prints a graph as a script, [`parse_script`] turns it back into an equivalent graph.
+///
+/// Pure nodes used more than once are printed at every use, unwired inputs are printed as `_`.
+/// Graphs the syntax can't express fail: a set without a target, a field node without a field
+/// and a flow that loops back on itself.
+pub fn print_script(snarl: &Snarl<ScriptNode>) -> Result<String, TextScriptError> {
+    let root = snarl
+        .node_ids()
+        .find(|(_, node)| matches!(node, ScriptNode::Query(_) | ScriptNode::SelfEntity(_)))
+        .ok_or_else(|| TextScriptError::Invalid("the graph has neither a query nor a self node".to_string()))?;
+    let mut printer = Printer {
+        snarl,
+        sources: snarl.wires().map(|(out_pin, in_pin)| (in_pin, out_pin)).collect(),
+        names: HashMap::new(),
+    };
+    let (root_id, root_node) = root;
+    let (keyword, query_node) = match root_node {
+        ScriptNode::SelfEntity(query_node) => ("self", query_node),
+        ScriptNode::Query(query_node) => ("query", query_node),
+        _ => unreachable!(),
+    };
+    let mut bindings = vec![];
+    for (index, (_, _, type_info)) in query_node.components.iter().enumerate() {
+        let type_name = type_info.type_path_table().short_path();
+        let name = snake_case(type_name);
+        printer.names.insert(OutPinId { node: root_id, output: index + 1 }, name.clone());
+        bindings.push(format!("{}: {}", name, type_name));
+    }
+
+    let mut body = String::new();
+    for (node_id, node) in snarl.node_ids() {
+        if let ScriptNode::Parameter(parameter_node) = node {
+            writeln!(
+                body,
+                "    param {}: {} = {}",
+                parameter_node.name,
+                parameter_node.value.reflect_short_type_path(),
+                format_value(parameter_node.value.as_ref())
+            )
+            .unwrap();
+            printer.names.insert(OutPinId { node: node_id, output: 0 }, parameter_node.name.clone());
+        }
+    }
+    let mut locals = 0;
+    let mut flow = OutPinId { node: root_id, output: 0 };
+    let mut visited = HashSet::from([root_id]);
+    while let Some(next) = snarl.wires().find(|(out_pin, _)| *out_pin == flow).map(|(_, in_pin)| in_pin.node) {
+        if !visited.insert(next) {
+            return Err(TextScriptError::Invalid(format!("the flow loops back to node {:?}", next)));
+        }
+        match &snarl[next] {
+            ScriptNode::Set(_) => {
+                if !printer.sources.contains_key(&InPinId { node: next, input: 1 }) {
+                    return Err(TextScriptError::Invalid(format!("node {:?}: a set needs a target", next)));
+                }
+                let target = printer.input(next, 1)?;
+                let value = printer.input(next, 2)?;
+                writeln!(body, "    {} = {}", target, value).unwrap();
+            }
+            ScriptNode::Function(function_node) => {
+                let call = printer.call(next, function_node)?;
+                let used = snarl.wires().any(|(out_pin, _)| out_pin.node == next && out_pin.output != 0);
+                if used {
+                    let name = format!("v{}", locals);
+                    locals += 1;
+                    writeln!(body, "    let {} = {}", name, call).unwrap();
+                    for index in 0..function_node.returns.len() {
+                        let element = match function_node.returns {
+                            FunctionReturn::Tuple(_) => format!("{}.{}", name, index),
+                            _ => name.clone(),
+                        };
+                        printer.names.insert(OutPinId { node: next, output: function_node.output_pin(index) }, element);
+                    }
+                } else {
+                    writeln!(body, "    {}", call).unwrap();
+                }
+            }
+            ScriptNode::Unresolved(unresolved) => return Err(unregistered(next, unresolved)),
+            _ => break,
+        }
+        flow = OutPinId { node: next, output: 0 };
+    }
+    Ok(format!("{} ({}) {{\n{}}}\n", keyword, bindings.join(", "), body))
+}
+
+/// A placeholder can't be printed as a call, its arguments aren't known.
+fn unregistered(node: NodeId, unresolved: &UnresolvedNode) -> TextScriptError {
+    TextScriptError::Invalid(format!("node {:?}: `{}` isn't registered", node, unresolved.name()))
+}
+
+struct Printer<'a> {
+    snarl: &'a Snarl<ScriptNode>,
+    sources: HashMap<InPinId, OutPinId>,
+    /// Outputs that are referred to by name.
+    names: HashMap<OutPinId, String>,
+}
+
+impl Printer<'_> {
+    fn input(&self, node: NodeId, input: usize) -> Result<String, TextScriptError> {
+        match self.sources.get(&InPinId { node, input }) {
+            None => Ok("_".to_string()),
+            Some(source) => self.output(*source),
+        }
+    }
+
+    fn output(&self, pin: OutPinId) -> Result<String, TextScriptError> {
+        if let Some(name) = self.names.get(&pin) {
+            return Ok(name.clone());
+        }
+        Ok(match &self.snarl[pin.node] {
+            ScriptNode::Field(field_node) => {
+                let name = field_node
+                    .name
+                    .as_deref()
+                    .ok_or_else(|| TextScriptError::Invalid(format!("node {:?}: no field is picked", pin.node)))?;
+                format!("{}.{}", self.input(pin.node, 0)?, name)
+            }
+            ScriptNode::Function(function_node) => {
+                let call = self.call(pin.node, function_node)?;
+                match function_node.returns {
+                    FunctionReturn::Tuple(_) => format!("{}.{}", call, pin.output - function_node.output_pin(0)),
+                    _ => call,
+                }
+            }
+            ScriptNode::TypeCreation(type_creation_node) => format_value(type_creation_node.value.as_ref()),
+            ScriptNode::Unresolved(unresolved) => return Err(unregistered(pin.node, unresolved)),
+            _ => "_".to_string(),
+        })
+    }
+
+    fn call(&self, node: NodeId, function_node: &FunctionNode) -> Result<String, TextScriptError> {
+        let args = function_node
+            .args()
+            .into_iter()
+            .map(|arg| self.input(node, function_node.input_pin(arg)))
+            .collect::<Result<Vec<_>, _>>()?;
+        // overload sets by their name, functions by their stable id
+        let name = function_node.overload.as_deref().unwrap_or(function_node.function_id());
+        Ok(format!("{}({})", name, args.join(", ")))
+    }
+}
+
+fn format_value(value: &dyn Reflect) -> String {
+    match value.reflect_ref() {
+        ReflectRef::Struct(dyn_struct) => format!(
+            "{}({})",
+            value.reflect_short_type_path(),
+            dyn_struct.iter_fields().map(format_value).collect::<Vec<_>>().join(", ")
+        ),
+        ReflectRef::TupleStruct(tuple_struct) => format!(
+            "{}({})",
+            value.reflect_short_type_path(),
+            tuple_struct.iter_fields().map(format_value).collect::<Vec<_>>().join(", ")
+        ),
+        _ => {
+            let float = value
+                .downcast_ref::<f32>()
+                .map(|number| ("f32", *number as f64))
+                .or_else(|| value.downcast_ref::<f64>().map(|number| ("f64", *number)));
+            if let Some(string) = value.downcast_ref::<String>() {
+                format!("{:?}", string)
+            } else if let Some((float_type, number)) = float.filter(|(_, number)| !number.is_finite()) {
+                let name = if number.is_nan() {
+                    "NAN"
+                } else if number > 0.0 {
+                    "INFINITY"
+                } else {
+                    "NEG_INFINITY"
+                };
+                format!("{}::{}", float_type, name)
+            } else {
+                // numbers and bools debug print as their literal
+                format!("{:?}", value)
+            }
+        }
+    }
+}
+
+fn snake_case(name: &str) -> String {
+    let mut snake = String::new();
+    for (index, c) in name.chars().enumerate() {
+        if c.is_uppercase() {
+            if index != 0 {
+                snake.push('_');
+            }
+            snake.extend(c.to_lowercase());
+        } else {
+            snake.push(c);
+        }
+    }
+    snake
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::AppTypeRegistry;
+    use crate::registry::FunctionDescriptor;
+    use crate::test_support::{position_world, sub};
+    use super::*;
+
+    #[derive(Reflect)]
+    struct Pair(f32, f32);
+
+    fn scale(value: &mut f32, factor: f32) {
+        *value *= factor;
+    }
+
+    fn pair(value: f32) -> (Pair, f32) {
+        (Pair(value, -value), value)
+    }
+
+    const SOURCE: &str = "query (position: Position) {
+    param speed: f32 = 2.0
+    param offset: f32 = -1.5
+    param tiny: f32 = 1e-7
+    param limit: f32 = f32::INFINITY
+    param floor: f64 = f64::NEG_INFINITY
+    param unset: f32 = f32::NAN
+    position.x = sub(position.y, speed)
+    scale(position.x, 2.0)
+    let v0 = pair(offset)
+    position.y = v0.0.1
+}
+";
+
+    #[test]
+    fn printed_script_parses_to_the_same_graph() {
+        let (world, component_map) = position_world();
+        world.resource::<AppTypeRegistry>().write().register::<Pair>();
+        world.resource::<AppTypeRegistry>().write().register::<(Pair, f32)>();
+        let type_registry = world.resource::<AppTypeRegistry>().read();
+        let mut function_registry = FunctionRegistry::default();
+        function_registry.register("sub", sub, FunctionDescriptor::new().pure());
+        function_registry.register("scale", scale, FunctionDescriptor::new());
+        function_registry.register("pair", pair, FunctionDescriptor::new());
+
+        let parsed = parse_script(SOURCE, &function_registry, &type_registry, &component_map).unwrap();
+        let printed = print_script(&parsed).unwrap();
+        assert_eq!(printed, SOURCE);
+        let reparsed = parse_script(&printed, &function_registry, &type_registry, &component_map).unwrap();
+        assert_eq!(print_script(&reparsed).unwrap(), SOURCE);
+    }
+
+    #[test]
+    fn minus_only_signs_numbers_and_indices_are_not_floats() {
+        let tokens = |source: &str| lex(source).unwrap().into_iter().map(|(token, _)| token).collect::<Vec<_>>();
+        assert_eq!(
+            tokens("v.0.1"),
+            vec![
+                Token::Ident("v".to_string()),
+                Token::Punct('.'),
+                Token::Number("0".to_string()),
+                Token::Punct('.'),
+                Token::Number("1".to_string()),
+            ]
+        );
+        assert_eq!(tokens("-2.5e-3"), vec![Token::Number("-2.5e-3".to_string())]);
+        // no operators, so `-b` isn't a number either
+        assert!(lex("f(a, -b)").is_err());
+    }
+}
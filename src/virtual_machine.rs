@@ -1,151 +1,448 @@
 use std::any::Any;
+use std::cell::{Ref, RefMut};
 use std::collections::HashMap;
-use std::ptr::NonNull;
+use std::fmt::{Display, Formatter};
 use bevy::ecs::component::ComponentId;
-use bevy::ecs::world::FilteredEntityMut;
-use bevy::prelude::{AppTypeRegistry, Mut, QueryBuilder, Reflect, Res, Vec3, World};
-use bevy::ptr::PtrMut;
+use bevy::prelude::{AppTypeRegistry, Entity, Mut, QueryBuilder, Reflect, Res, Vec3, World};
 use bevy::reflect::func::{Arg, ArgList, Return};
 use bevy::reflect::{ReflectFromPtr, ReflectMut, ReflectRef, TypeInfo};
 use crate::{functions};
-use crate::indirect_stack::{IndirectStack, StackValue};
-use crate::registry::FunctionRegistry;
+use crate::indirect_stack::{IndirectStack, StackError, StackHandle, StackValue};
+use crate::registry::{FunctionKind, FunctionRegistry, MainThreadFunctions};
+use crate::script_component::ScriptParameters;
 
 #[derive(Debug)]
-pub enum Bytecode<'a> {
-    Push(StackValue<'a>),
+pub enum Bytecode {
+    Push(StackValue),
     Pop,
+    /// Calls a registered function by its stable id, see [`FunctionDescriptor::id`](crate::registry::FunctionDescriptor::id).
+    ///
+    /// Arguments are pushed in parameter order, so the first parameter (index 0 in the
+    /// function's `FunctionInfo`) is the deepest and the last parameter sits on top of the stack.
     Call(String),
-    GetField(usize, String),
-    SetField(usize),
+    GetField(StackHandle, String),
+    SetField(StackHandle),
     Query {
         components: Vec<(String, ComponentId, TypeInfo)>,
     },
-    Copy(usize)
+    /// Like [`Bytecode::Query`] but only for the entity the script is attached to.
+    SelfEntity {
+        components: Vec<(String, ComponentId, TypeInfo)>,
+    },
+    Copy(StackHandle),
+    /// Pushes the entity's override of an exposed parameter, or `default` if it has none.
+    Parameter {
+        name: String,
+        default: Box<dyn Reflect>,
+    },
+    /// Pushes a shared borrow of a slot, used for arguments taken by `&T`.
+    Ref(StackHandle),
+    /// Pushes an exclusive borrow of a slot, used for arguments taken by `&mut T`.
+    Mut(StackHandle),
 }
 
-impl Clone for Bytecode<'_> {
+impl Clone for Bytecode {
     fn clone(&self) -> Self {
         match self {
-            Bytecode::Push(val) => {
-                match val {
-                    StackValue::Owned(owned) => {
-                        Bytecode::Push(StackValue::Owned(owned.clone_value()))
-                    }
-                    _ => panic!("can't clone if it's not owned"),
-                }
-            }
+            Bytecode::Push(val) => Bytecode::Push(val.clone()),
             Bytecode::Pop => Bytecode::Pop,
             Bytecode::Call(name) => Bytecode::Call(name.clone()),
             Bytecode::GetField(i, name) => Bytecode::GetField(*i, name.clone()),
             Bytecode::SetField(i) => Bytecode::SetField(*i),
             Bytecode::Query { components } => Bytecode::Query { components: components.clone() },
+            Bytecode::SelfEntity { components } => Bytecode::SelfEntity { components: components.clone() },
             Bytecode::Copy(i) => Bytecode::Copy(*i),
+            Bytecode::Parameter { name, default } => Bytecode::Parameter {
+                name: name.clone(),
+                default: default.clone_value(),
+            },
+            Bytecode::Ref(i) => Bytecode::Ref(*i),
+            Bytecode::Mut(i) => Bytecode::Mut(*i),
         }
     }
 }
 
+#[derive(Debug)]
+pub enum VmError {
+    Stack(StackError),
+    UnknownFunction(String),
+    Call {
+        function: String,
+        message: String,
+    },
+    MissingReflectFromPtr(String),
+    /// A main-thread function was called without [`MainThreadFunctions`].
+    MainThreadOnly(String),
+    /// A program starting with [`Bytecode::SelfEntity`] was run without an entity.
+    MissingSelf,
+    /// Bytecode the compiler doesn't produce, e.g. from a corrupt program file.
+    InvalidProgram(String),
+}
+
+impl Display for VmError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VmError::Stack(err) => write!(f, "{}", err),
+            VmError::UnknownFunction(name) => write!(f, "no function named `{}` is registered", name),
+            VmError::Call { function, message } => write!(f, "calling `{}` failed: {}", function, message),
+            VmError::MissingReflectFromPtr(type_path) => write!(f, "`{}` is not registered with ReflectFromPtr", type_path),
+            VmError::MainThreadOnly(name) => write!(f, "`{}` can only be called on the main thread", name),
+            VmError::MissingSelf => write!(f, "the script has a self node but isn't attached to an entity"),
+            VmError::InvalidProgram(message) => write!(f, "invalid program: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for VmError {}
+
+impl From<StackError> for VmError {
+    fn from(err: StackError) -> Self {
+        VmError::Stack(err)
+    }
+}
+
+/// An argument popped off the stack, either owned or borrowed from a slot below it.
+enum PendingArg<'s> {
+    Owned(Option<Box<dyn Reflect>>),
+    Ref(Ref<'s, dyn Reflect>),
+    Mut(RefMut<'s, dyn Reflect>),
+}
+
+fn pop(indirect_stack: &mut IndirectStack) -> Result<StackValue, VmError> {
+    indirect_stack
+        .pop()
+        .ok_or_else(|| VmError::InvalidProgram("popped an empty stack".to_string()))
+}
+
+/// [`Reflect::apply`] panics on a type mismatch, which only a corrupt program can cause.
+fn apply(target: &mut dyn Reflect, value: &dyn Reflect) -> Result<(), VmError> {
+    let (target_type, value_type) = (type_path(target), type_path(value));
+    if target_type != value_type {
+        return Err(VmError::InvalidProgram(format!("can't set a `{}` to a `{}`", target_type, value_type)));
+    }
+    target.apply(value);
+    Ok(())
+}
+
+/// The type a value stands for, dynamic values report the type they represent.
+fn type_path(value: &dyn Reflect) -> &str {
+    value
+        .get_represented_type_info()
+        .map(|type_info| type_info.type_path())
+        .unwrap_or_else(|| value.reflect_type_path())
+}
+
+fn by_value() -> VmError {
+    VmError::InvalidProgram("components can't be passed by value".to_string())
+}
+
+/// Whether the program calls a function of the main-thread tier, only then does [`run`] need
+/// the [`MainThreadFunctions`].
+pub fn uses_main_thread(instructions: &[Bytecode], function_registry: &FunctionRegistry) -> bool {
+    instructions.iter().any(|instruction| match instruction {
+        Bytecode::Call(function) => function_registry
+            .resolve(function)
+            .and_then(|name| function_registry.functions.get(name))
+            .is_some_and(|registered| matches!(registered.kind, FunctionKind::MainThread(_))),
+        _ => false,
+    })
+}
+
+/// The exposed parameters of a program and their defaults.
+pub fn parameters(instructions: &[Bytecode]) -> impl Iterator<Item = (&str, &dyn Reflect)> {
+    instructions.iter().filter_map(|instruction| match instruction {
+        Bytecode::Parameter { name, default } => Some((name.as_str(), default.as_ref())),
+        _ => None,
+    })
+}
+
+/// Runs a program on every entity its query matches.
+///
+/// The registry is only read, but the program still needs the whole world: which components it
+/// touches is only known once it runs and world functions get `&mut World`, so there is no access
+/// to declare to the scheduler and scripts run from exclusive systems.
+pub fn run(instructions: &[Bytecode], function_registry: &FunctionRegistry, main_thread: Option<&mut MainThreadFunctions>, world: &mut World) -> Result<(), VmError> {
+    run_program(instructions, None, function_registry, main_thread, world)
+}
+
+/// Runs a program on the entity its [`Script`](crate::script_component::Script) is attached to,
+/// programs starting with a query still run on every matching entity.
+pub fn run_on_entity(instructions: &[Bytecode], entity: Entity, function_registry: &FunctionRegistry, main_thread: Option<&mut MainThreadFunctions>, world: &mut World) -> Result<(), VmError> {
+    run_program(instructions, Some(entity), function_registry, main_thread, world)
+}
 
-pub fn run(mut instructions: Vec<Bytecode>, function_registry: &mut FunctionRegistry, world: &mut World) {
+fn run_program(instructions: &[Bytecode], self_entity: Option<Entity>, function_registry: &FunctionRegistry, mut main_thread: Option<&mut MainThreadFunctions>, world: &mut World) -> Result<(), VmError> {
 
     //println!("{:#?}", instructions);
 
-    world.resource_scope(|world, registry: Mut<AppTypeRegistry>| {
-        let registry = registry.read();;
+    world.resource_scope(|world, registry: Mut<AppTypeRegistry>| -> Result<(), VmError> {
+        let registry = registry.read();
 
-        // first instruction is a query
-        match instructions.remove(0) {
-            Bytecode::Query { components } => {
-                let mut builder = QueryBuilder::<FilteredEntityMut>::new(world);
-                for (name, id, type_info) in &components {
-                    builder.mut_id(*id);
-                }
-                let mut query = builder.build();
-                for mut filtered_entity in query.iter_mut(world) {
-                    let instructions = instructions.clone();
-                    let mut indirect_stack = IndirectStack::default();
-
-                    let ids = filtered_entity.components().map(|a| a).collect::<Vec<_>>();
-
-                    for id in ids {
-                        let temp = filtered_entity.get_mut_by_id(id);
-                        let mut temp2 = temp.unwrap();
-                        let ptr = NonNull::new(temp2.as_mut().as_ptr()).unwrap();
-                        let ptr = unsafe { std::mem::transmute(ptr)};
-                        for (_, id2, type_info) in &components {
-                            if *id2 == id {
-                                let reflect_data = registry.get(type_info.type_id()).unwrap();
-                                let reflect_from_ptr = reflect_data.data::<ReflectFromPtr>().unwrap();
-                                let value = unsafe { reflect_from_ptr.as_reflect_mut(ptr) };
-                                indirect_stack.push_mut(value);
-                                break;
-                            }
-                        }
+        // first instruction is the root, a query or the entity the script is attached to
+        let (root, instructions) = instructions
+            .split_first()
+            .ok_or_else(|| VmError::InvalidProgram("the program is empty".to_string()))?;
+        let (components, is_query) = match root {
+            Bytecode::Query { components } => (components, true),
+            Bytecode::SelfEntity { components } => (components, false),
+            other => return Err(VmError::InvalidProgram(format!("the program starts with {:?} instead of a query or self", other))),
+        };
+        let mut from_ptrs = vec![];
+        for (_, _, type_info) in components {
+            let reflect_from_ptr = registry
+                .get(type_info.type_id())
+                .and_then(|reflect_data| reflect_data.data::<ReflectFromPtr>())
+                .ok_or_else(|| VmError::MissingReflectFromPtr(type_info.type_path().to_string()))?;
+            from_ptrs.push(reflect_from_ptr.clone());
+        }
+        // the stack casts component pointers with these, the ids have to be of the same types
+        for (_, id, type_info) in components {
+            let component_type = world.components().get_info(*id).and_then(|info| info.type_id());
+            if component_type != Some(type_info.type_id()) {
+                return Err(VmError::InvalidProgram(format!("component {:?} isn't a `{}`", id, type_info.type_path())));
+            }
+        }
+
+        let entities = if is_query {
+            let mut builder = QueryBuilder::<Entity>::new(world);
+            for (name, id, type_info) in components {
+                builder.with_id(*id);
+            }
+            let mut query = builder.build();
+            query.iter(world).collect::<Vec<_>>()
+        } else {
+            vec![self_entity.ok_or(VmError::MissingSelf)?]
+        };
+
+        // the stack is the only thing touching the world from here on
+        let world = world.as_unsafe_world_cell();
+        for entity in entities {
+            // copied so world functions can't change them under us
+            // SAFETY: nothing else accesses the world between entities.
+            let parameters = unsafe { world.get_entity(entity).and_then(|entity| entity.get::<ScriptParameters>()) }
+                .map(|parameters| {
+                    parameters
+                        .0
+                        .iter()
+                        .map(|(name, value)| (name.clone(), value.clone_value()))
+                        .collect::<HashMap<_, _>>()
+                })
+                .unwrap_or_default();
+            // SAFETY: the stack is dropped before the next entity and world functions only get the
+            // world while no slot is borrowed.
+            let mut indirect_stack = unsafe { IndirectStack::new(world) };
+
+            for ((_, id, _), from_ptr) in components.iter().zip(&from_ptrs) {
+                // SAFETY: `from_ptr` comes from the registration of the component's type, checked above.
+                unsafe { indirect_stack.push_component(entity, *id, from_ptr.clone()) }?;
+            }
+            // owned values are consumed by the stack, so every entity gets its own copy
+            for instruction in instructions.iter().cloned() {
+                match instruction {
+                    Bytecode::Push(StackValue::Owned(owned)) => {
+                        indirect_stack.push_owned(owned);
                     }
-                    for instruction in instructions {
-                        match instruction {
-                            Bytecode::Push(val) => {
-                                indirect_stack.push(val);
-                            }
-                            Bytecode::Pop => {
-                                indirect_stack.pop();
+                    Bytecode::Push(value) => {
+                        return Err(VmError::InvalidProgram(format!("only owned values can be pushed, not {:?}", value)));
+                    }
+                    Bytecode::Pop => {
+                        pop(&mut indirect_stack)?;
+                    }
+                    Bytecode::Call(function) => {
+                        let name = function_registry.resolve(&function).ok_or_else(|| VmError::UnknownFunction(function.clone()))?.to_string();
+                        let registered = function_registry.functions.get(&name).unwrap();
+                        let arg_number = registered.signature().arg_count();
+                        let mut popped = vec![];
+                        for _ in 0..arg_number {
+                            popped.push(pop(&mut indirect_stack)?);
+                        }
+                        // popped last parameter first
+                        popped.reverse();
+                        let mut pending = vec![];
+                        for value in popped {
+                            pending.push(match value {
+                                StackValue::Owned(awa) => PendingArg::Owned(Some(awa)),
+                                StackValue::Component { .. } => return Err(by_value()),
+                                StackValue::InternalReference { name, parent } => {
+                                    PendingArg::Mut(indirect_stack.get_field_mut(parent, name)?)
+                                }
+                                StackValue::Ref(target) => PendingArg::Ref(indirect_stack.get(target)?),
+                                StackValue::Mut(target) => PendingArg::Mut(indirect_stack.get_mut(target)?),
+                            });
+                        }
+                        let returned = match &registered.kind {
+                            FunctionKind::World(world_function) => {
+                                // world functions get owned arguments, the borrows have to end before the world is handed out.
+                                // They only take owned values, so nothing has to be written back.
+                                let args = pending
+                                    .iter_mut()
+                                    .map(|arg| match arg {
+                                        PendingArg::Owned(owned) => owned.take().unwrap(),
+                                        PendingArg::Ref(r#ref) => r#ref.clone_value(),
+                                        PendingArg::Mut(r#mut) => r#mut.clone_value(),
+                                    })
+                                    .collect();
+                                drop(pending);
+                                // SAFETY: the stack only reaches into the world through slot borrows, those never
+                                // outlive an instruction and the ones of this call were just dropped.
+                                let world = unsafe { world.world_mut() };
+                                world_function.call(args, world).map_err(|message| VmError::Call {
+                                    function: function.clone(),
+                                    message,
+                                })?
                             }
-                            Bytecode::Call(function) => {
-                                let func = function_registry.0.get_mut(&function).unwrap();
-                                let arg_number = func.info().arg_count();
+                            kind => {
+                                let mut built;
+                                let func = match kind {
+                                    FunctionKind::Reflected(reflected) => {
+                                        built = reflected.function();
+                                        &mut built
+                                    }
+                                    FunctionKind::MainThread(_) => main_thread
+                                        .as_deref_mut()
+                                        .and_then(|functions| functions.0.get_mut(&name))
+                                        .ok_or_else(|| VmError::MainThreadOnly(function.clone()))?,
+                                    FunctionKind::World(_) => unreachable!(),
+                                };
                                 let mut args = ArgList::new();
-                                for _ in 0..arg_number {
-                                    args = args.push(match indirect_stack.pop().unwrap() {
-                                        StackValue::Owned(awa) => {
-                                            Arg::Owned(awa)
-                                        }
-                                        StackValue::Mut(uwu) => {
-                                            Arg::Mut(uwu)
-                                        }
-                                        StackValue::InternalReference { name, parent } => {
-                                            unsafe { Arg::Mut(indirect_stack.get_mut_internal_from_ref(parent, name).unwrap()) }
-                                        }
+                                for arg in pending.iter_mut() {
+                                    args = args.push(match arg {
+                                        PendingArg::Owned(owned) => Arg::Owned(owned.take().unwrap()),
+                                        PendingArg::Ref(r#ref) => Arg::Ref(&**r#ref),
+                                        PendingArg::Mut(r#mut) => Arg::Mut(&mut **r#mut),
                                     });
                                 }
-                                match func.call(args).unwrap() {
-                                    Return::Unit => {}
-                                    Return::Owned(owned) => indirect_stack.push_owned(owned),
-                                    Return::Ref(r#ref) => todo!(),
-                                    Return::Mut(r#mut) => indirect_stack.push_mut(r#mut),
+                                // borrowed returns can't outlive the arguments they came from
+                                let returned = match func.call(args) {
+                                    Ok(Return::Unit) => None,
+                                    Ok(Return::Owned(owned)) => Some(owned),
+                                    Ok(Return::Ref(r#ref)) => Some(r#ref.clone_value()),
+                                    Ok(Return::Mut(r#mut)) => Some(r#mut.clone_value()),
+                                    Err(err) => return Err(VmError::Call {
+                                        function,
+                                        message: format!("{:?}", err),
+                                    }),
                                 };
+                                drop(pending);
+                                returned
                             }
-                            Bytecode::GetField(index, field_name) => {
-                                indirect_stack.push_internal_ref(field_name, index);
-                            },
-                            Bytecode::SetField(index) => {
-                                let first= indirect_stack.pop().unwrap();
-                                match first {
-                                    StackValue::Owned(owned) => {
-                                        unsafe { indirect_stack.get_mut_internal(index).unwrap().apply(owned.as_ref()) };
-                                    }
-                                    StackValue::Mut(dyn_reflect) => {
-                                        unsafe {
-                                            indirect_stack.get_mut_internal(index).unwrap().apply(dyn_reflect.as_reflect());
-                                        }
-                                    },
-                                    StackValue::InternalReference { name, parent } => {
-                                        unsafe {
-                                            let first = indirect_stack.get_internal_from_ref(parent, name).unwrap();
-                                            indirect_stack.get_mut_internal(index).unwrap().apply(first);
-                                        }
-                                    },
-                                }
-                            },
-                            Bytecode::Query { .. } => panic!("shouldn't have a second query"),
-                            Bytecode::Copy(index) => {
-                                let val = unsafe { indirect_stack.get_ref_internal(index).unwrap() }.clone_value();
-                                indirect_stack.push_owned(val);
-                            },
+                        };
+                        if let Some(returned) = returned {
+                            indirect_stack.push_owned(returned);
                         }
                     }
+                    Bytecode::GetField(index, field_name) => {
+                        indirect_stack.push_internal_ref(field_name, index)?;
+                    },
+                    Bytecode::SetField(index) => {
+                        let first = pop(&mut indirect_stack)?;
+                        let source = match first {
+                            StackValue::Owned(owned) => {
+                                apply(&mut *indirect_stack.get_mut(index)?, owned.as_ref())?;
+                                continue;
+                            }
+                            StackValue::Component { .. } => return Err(by_value()),
+                            StackValue::InternalReference { name, parent } => indirect_stack.get_field(parent, name)?,
+                            StackValue::Ref(target) | StackValue::Mut(target) => indirect_stack.get(target)?,
+                        };
+                        match indirect_stack.get_mut(index) {
+                            Ok(mut target) => apply(&mut *target, &*source)?,
+                            // source and target share a slot (e.g. two fields of one component), so copy
+                            Err(StackError::AlreadyBorrowed(_)) => {
+                                let value = source.clone_value();
+                                drop(source);
+                                apply(&mut *indirect_stack.get_mut(index)?, value.as_ref())?;
+                            }
+                            Err(err) => return Err(err.into()),
+                        }
+                    },
+                    Bytecode::Query { .. } | Bytecode::SelfEntity { .. } => {
+                        return Err(VmError::InvalidProgram("only the first instruction can be a query or self".to_string()));
+                    }
+                    Bytecode::Copy(index) => {
+                        let val = indirect_stack.get(index)?.clone_value();
+                        indirect_stack.push_owned(val);
+                    },
+                    Bytecode::Parameter { name, default } => {
+                        let value = parameters
+                            .get(&name)
+                            .filter(|value| value.reflect_type_path() == default.reflect_type_path())
+                            .map(|value| value.clone_value())
+                            .unwrap_or(default);
+                        indirect_stack.push_owned(value);
+                    },
+                    Bytecode::Ref(index) => {
+                        indirect_stack.push_ref(index)?;
+                    },
+                    Bytecode::Mut(index) => {
+                        indirect_stack.push_mut(index)?;
+                    },
                 }
             }
-            awa => panic!("first instruction should be a query, instead: {:#?}", awa),
         }
-    });
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::reflect::Typed;
+    use crate::registry::FunctionDescriptor;
+    use crate::test_support::{position_world, sub, Position};
+    use super::*;
+
+    fn lerp(from: f32, to: f32, t: f32) -> f32 {
+        from + (to - from) * t
+    }
+
+    /// Runs `instructions` after a query for `Position`, which is slot 0, and returns the
+    /// position afterwards.
+    fn run_on_position(instructions: Vec<Bytecode>) -> Position {
+        let (mut world, _) = position_world();
+        let entity = world.spawn(Position { x: 1.0, y: 2.0 }).id();
+        let component_id = world.component_id::<Position>().unwrap();
+        let mut function_registry = FunctionRegistry::default();
+        function_registry.register("sub", sub, FunctionDescriptor::new());
+        function_registry.register("lerp", lerp, FunctionDescriptor::new());
+
+        let mut program = vec![Bytecode::Query {
+            components: vec![("Position".to_string(), component_id, Position::type_info().clone())],
+        }];
+        program.extend(instructions);
+        run(&program, &function_registry, None, &mut world).unwrap();
+        world.entity_mut(entity).take::<Position>().unwrap()
+    }
+
+    #[test]
+    fn sub_gets_its_arguments_in_parameter_order() {
+        let position = run_on_position(vec![
+            Bytecode::GetField(StackHandle(0), "x".to_string()),
+            Bytecode::GetField(StackHandle(0), "y".to_string()),
+            // sub(x, y)
+            Bytecode::Copy(StackHandle(1)),
+            Bytecode::Copy(StackHandle(2)),
+            Bytecode::Call("sub".to_string()),
+            Bytecode::Ref(StackHandle(3)),
+            Bytecode::SetField(StackHandle(1)),
+        ]);
+        assert_eq!(position.x, -1.0);
+    }
+
+    #[test]
+    fn lerp_gets_its_arguments_in_parameter_order() {
+        let position = run_on_position(vec![
+            Bytecode::Push(StackValue::Owned(Box::new(0.0_f32))),
+            Bytecode::Push(StackValue::Owned(Box::new(10.0_f32))),
+            Bytecode::Push(StackValue::Owned(Box::new(0.25_f32))),
+            // lerp(from: 0, to: 10, t: 0.25)
+            Bytecode::Copy(StackHandle(1)),
+            Bytecode::Copy(StackHandle(2)),
+            Bytecode::Copy(StackHandle(3)),
+            Bytecode::Call("lerp".to_string()),
+            Bytecode::GetField(StackHandle(0), "y".to_string()),
+            Bytecode::Ref(StackHandle(4)),
+            Bytecode::SetField(StackHandle(5)),
+        ]);
+        assert_eq!(position.y, 2.5);
+    }
 }